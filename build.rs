@@ -6,6 +6,8 @@ use std::{
 fn main() {
     let user_src_path = build_user_bins();
     println!("cargo:rerun-if-changed={}", user_src_path.display());
+    gen_ksyms();
+    build_disk_image();
 
     println!("cargo:rustc-link-arg-bin=os=--script=src/kernel/kernel.ld");
 }
@@ -14,6 +16,10 @@ fn build_user_bins() -> PathBuf {
     let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".into());
     let mut cmd = Command::new(cargo);
     cmd.arg("install").arg("OS1000lineUser");
+    // User programs must use the same syscall calling convention as the kernel.
+    if std::env::var_os("CARGO_FEATURE_LEGACY_SYSCALL_ABI").is_some() {
+        cmd.args(["--features", "legacy-syscall-abi"]);
+    }
     let user_path = Path::new(env!("CARGO_MANIFEST_DIR"))
         .join("src")
         .join("user");
@@ -44,7 +50,8 @@ fn build_user_bins() -> PathBuf {
             .map(|path| path.as_ref().unwrap().path());
         for bin in bins.into_iter() {
             if bin.extension().is_none() {
-                objcopy.run(bin);
+                objcopy.run(bin.clone());
+                append_user_symbols(&bin);
             };
         }
 
@@ -80,3 +87,171 @@ impl Objcopy {
         assert!(status.success());
     }
 }
+
+/// Generate the kernel symbol table `$OUT_DIR/ksyms.bin`. (format: src/abi/symbols.rs)
+///
+/// The symbols are read from the kernel of the previous link given by `KSYMS_FROM`(see Makefile).
+/// Without it, an empty table is embedded and backtraces are not symbolized.
+fn gen_ksyms() {
+    println!("cargo:rerun-if-env-changed=KSYMS_FROM");
+    let symbols = match std::env::var_os("KSYMS_FROM") {
+        Some(elf) if Path::new(&elf).exists() => read_symbols(Path::new(&elf)),
+        _ => Vec::new(),
+    };
+    let out = Path::new(&std::env::var("OUT_DIR").unwrap()).join("ksyms.bin");
+    std::fs::write(out, encode_symbols(&symbols)).unwrap();
+}
+
+/// Files packed into the disk image (see the runner in .cargo/config.toml)
+const DISK_DIR: &str = "disk";
+const DISK_IMAGE: &str = "disk.tar";
+const DISK_IMAGE_MIN_SIZE: usize = 64 * 1024;
+
+/// Pack the regular files of `DISK_DIR` into the ustar archive `DISK_IMAGE`.
+fn build_disk_image() {
+    println!("cargo:rerun-if-changed={}", DISK_DIR);
+    let mut entries: Vec<_> = std::fs::read_dir(DISK_DIR)
+        .map(|dir| {
+            dir.filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .collect()
+        })
+        .unwrap_or_default();
+    entries.sort();
+
+    let mut image = Vec::new();
+    for path in entries.iter().filter(|path| path.is_file()) {
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let data = std::fs::read(path).unwrap();
+        image.extend(ustar_header(name, data.len()));
+        image.extend(&data);
+        // Pad to the block boundary.
+        image.resize(image.len() + (512 - data.len() % 512) % 512, 0);
+    }
+    // End of archive: 2 zero blocks. The rest is the free space for the writes by the kernel.
+    image.resize((image.len() + 2 * 512).max(DISK_IMAGE_MIN_SIZE), 0);
+    std::fs::write(DISK_IMAGE, image).unwrap();
+}
+
+/// 512 bytes ustar header of a regular file
+fn ustar_header(name: &str, size: usize) -> [u8; 512] {
+    assert!(name.len() < 100, "too long file name: {}", name);
+    let mut header = [0u8; 512];
+    let mut put = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    put(0, name.as_bytes());
+    put(100, b"0000644\0"); // mode
+    put(108, b"0000000\0"); // uid
+    put(116, b"0000000\0"); // gid
+    put(124, format!("{:011o}\0", size).as_bytes());
+    put(136, b"00000000000\0"); // mtime
+    put(148, b"        "); // checksum is computed with spaces
+    put(156, b"0"); // regular file
+    put(257, b"ustar\0");
+    put(263, b"00"); // version
+    let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+/// Base address of the user apps (src/user/user.ld)
+const USER_BASE: u32 = 0x1000000;
+
+/// Append the symbol table of the user app `elf` to its flat binary at `__syms`(src/user/user.ld),
+/// for the user panic backtrace.
+fn append_user_symbols(elf: &Path) {
+    let nm_output = nm(elf);
+    let syms_addr = nm_output
+        .lines()
+        .filter_map(|line| line.strip_suffix(" __syms"))
+        .find_map(|line| u32::from_str_radix(line.split(' ').next()?, 16).ok())
+        .expect("`__syms` is not defined by user.ld");
+    let symbols = collect_symbols(&nm_output);
+
+    let bin = elf.with_extension("bin");
+    let mut image = std::fs::read(&bin).unwrap();
+    // Between the file end & `__syms` is 0 filled (e.g. .bss when no .eh_frame).
+    image.resize((syms_addr - USER_BASE) as usize, 0);
+    image.extend(encode_symbols(&symbols));
+    std::fs::write(bin, image).unwrap();
+}
+
+/// (address, size, demangled name)
+type Symbol = (u32, u32, String);
+
+/// `rust-nm` output of `elf`: `<addr> [<size>] <type> <name>` lines
+fn nm(elf: &Path) -> String {
+    let output = Command::new("rust-nm")
+        .args([
+            "--defined-only",
+            "--print-size",
+            "--demangle",
+            "--numeric-sort",
+        ])
+        .arg(elf)
+        .output()
+        .expect("failed to run rust-nm");
+    assert!(output.status.success());
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// Read the function symbols of `elf`.
+fn read_symbols(elf: &Path) -> Vec<Symbol> {
+    collect_symbols(&nm(elf))
+}
+
+fn collect_symbols(nm_output: &str) -> Vec<Symbol> {
+    let mut symbols: Vec<Symbol> = nm_output.lines().filter_map(parse_nm_line).collect();
+    // Prefer the sized symbol(function) at the same address.
+    symbols.sort_by_key(|(addr, size, _)| (*addr, *size == 0));
+    symbols.dedup_by_key(|(addr, _, _)| *addr);
+    symbols
+}
+
+/// Parse `<addr> [<size>] <type> <name>`. Text symbols only.
+fn parse_nm_line(line: &str) -> Option<Symbol> {
+    let (addr, rest) = line.split_once(' ')?;
+    let (size, rest) = match rest.split_once(' ')? {
+        // No size(e.g. asm labels)
+        (ty, _) if ty.len() == 1 => ("0", rest),
+        (size, rest) => (size, rest),
+    };
+    let (ty, name) = rest.split_once(' ')?;
+    // Skip asm local labels(`.L*`) & linker script symbols(`__kernel_base`, ...)
+    if !matches!(ty, "T" | "t") || name.starts_with(".L") || (name.starts_with("__") && size == "0")
+    {
+        return None;
+    }
+    // Strip the LTO suffix: `core::fmt::write (.llvm.1234)`
+    let name = name.split(" (.llvm.").next()?;
+    // Strip the legacy mangling hash: `kernel::pages::map_page::h0123456789abcdef`
+    let name = match name.rsplit_once("::h") {
+        Some((path, hash)) if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            path
+        }
+        _ => name,
+    };
+    Some((
+        u32::from_str_radix(addr, 16).ok()?,
+        u32::from_str_radix(size, 16).ok()?,
+        name.to_string(),
+    ))
+}
+
+fn encode_symbols(symbols: &[Symbol]) -> Vec<u8> {
+    let mut entries = Vec::new();
+    let mut names = Vec::new();
+    for (addr, size, name) in symbols {
+        for field in [*addr, *size, names.len() as u32, name.len() as u32] {
+            entries.extend_from_slice(&field.to_le_bytes());
+        }
+        names.extend_from_slice(name.as_bytes());
+    }
+
+    let mut table = b"SYMS".to_vec();
+    table.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    table.extend(entries);
+    table.extend(names);
+    table
+}
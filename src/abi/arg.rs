@@ -0,0 +1,260 @@
+//! Encoding/decoding of system call arguments & return values to registers.
+use crate::conv::{ARG_REGS, RET_REGS};
+use crate::errno::Errno;
+
+/// Argument registers of one system call.
+///
+/// Arguments are pushed(user) and taken(kernel) in order, from a0.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RawArgs {
+    regs: [usize; ARG_REGS],
+    /// next register index
+    cursor: usize,
+}
+
+impl RawArgs {
+    /// For decoding. (kernel side)
+    pub const fn new(regs: [usize; ARG_REGS]) -> Self {
+        Self { regs, cursor: 0 }
+    }
+
+    pub fn regs(&self) -> &[usize; ARG_REGS] {
+        &self.regs
+    }
+
+    /// Append one register value.
+    ///
+    /// # Panics
+    /// More than `ARG_REGS` registers are pushed.
+    pub fn push(&mut self, value: usize) {
+        assert!(self.cursor < ARG_REGS, "too many syscall arguments");
+        self.regs[self.cursor] = value;
+        self.cursor += 1;
+    }
+
+    /// Take the next register value.
+    ///
+    /// # Errors
+    /// All registers have been already taken: EINVAL
+    pub fn take(&mut self) -> Result<usize, Errno> {
+        let value = *self.regs.get(self.cursor).ok_or(Errno::EINVAL)?;
+        self.cursor += 1;
+        Ok(value)
+    }
+}
+
+/// Type that can be passed in argument registers.
+pub trait SyscallArg: Sized {
+    fn encode(self, args: &mut RawArgs);
+
+    /// # Errors
+    /// Can't decode as `Self`: EINVAL
+    fn decode(args: &mut RawArgs) -> Result<Self, Errno>;
+}
+
+/// Return registers(a0, a1)
+pub type RawRet = [usize; RET_REGS];
+
+/// Type that can be returned in return registers.
+///
+/// NOTE: Errors are detected from a0 only, so a0 must not be in `-4095..=-1` on success.
+pub trait SyscallRet: Sized {
+    fn into_ret(self) -> RawRet;
+    fn from_ret(ret: RawRet) -> Self;
+}
+
+macro_rules! impl_int_arg {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl SyscallArg for $ty {
+                fn encode(self, args: &mut RawArgs) {
+                    args.push(self as usize);
+                }
+
+                fn decode(args: &mut RawArgs) -> Result<Self, Errno> {
+                    <$ty>::try_from(args.take()?).map_err(|_| Errno::EINVAL)
+                }
+            }
+
+            impl SyscallRet for $ty {
+                fn into_ret(self) -> RawRet {
+                    let mut ret = RawRet::default();
+                    ret[0] = self as usize;
+                    ret
+                }
+
+                fn from_ret(ret: RawRet) -> Self {
+                    ret[0] as $ty
+                }
+            }
+        )+
+    };
+}
+impl_int_arg!(usize, u8, u16, u32);
+
+macro_rules! impl_signed_arg {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl SyscallArg for $ty {
+                fn encode(self, args: &mut RawArgs) {
+                    args.push(self as usize);
+                }
+
+                fn decode(args: &mut RawArgs) -> Result<Self, Errno> {
+                    Ok(args.take()? as $ty)
+                }
+            }
+
+            impl SyscallRet for $ty {
+                fn into_ret(self) -> RawRet {
+                    let mut ret = RawRet::default();
+                    ret[0] = self as usize;
+                    ret
+                }
+
+                fn from_ret(ret: RawRet) -> Self {
+                    ret[0] as $ty
+                }
+            }
+        )+
+    };
+}
+impl_signed_arg!(isize, i32);
+
+impl SyscallArg for bool {
+    fn encode(self, args: &mut RawArgs) {
+        args.push(self as usize);
+    }
+
+    fn decode(args: &mut RawArgs) -> Result<Self, Errno> {
+        match args.take()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+}
+
+impl SyscallArg for char {
+    fn encode(self, args: &mut RawArgs) {
+        args.push(self as usize);
+    }
+
+    fn decode(args: &mut RawArgs) -> Result<Self, Errno> {
+        char::from_u32(args.take()? as u32).ok_or(Errno::EINVAL)
+    }
+}
+
+impl SyscallRet for () {
+    fn into_ret(self) -> RawRet {
+        RawRet::default()
+    }
+
+    fn from_ret(_ret: RawRet) -> Self {}
+}
+
+/// (a0, a1)
+#[cfg(not(feature = "legacy-abi"))]
+impl SyscallRet for (usize, usize) {
+    fn into_ret(self) -> RawRet {
+        [self.0, self.1]
+    }
+
+    fn from_ret(ret: RawRet) -> Self {
+        (ret[0], ret[1])
+    }
+}
+
+/// Read only user memory `[addr, addr + len)`, passed in 2 registers.
+///
+/// For the kernel it's just a range. Access it through `uaccess`(validated copy).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct UserBuf {
+    pub addr: usize,
+    pub len: usize,
+}
+
+/// Writable user memory `[addr, addr + len)`, passed in 2 registers.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct UserBufMut {
+    pub addr: usize,
+    pub len: usize,
+}
+
+impl From<&[u8]> for UserBuf {
+    fn from(buf: &[u8]) -> Self {
+        Self {
+            addr: buf.as_ptr() as usize,
+            len: buf.len(),
+        }
+    }
+}
+
+impl From<&mut [u8]> for UserBufMut {
+    fn from(buf: &mut [u8]) -> Self {
+        Self {
+            addr: buf.as_mut_ptr() as usize,
+            len: buf.len(),
+        }
+    }
+}
+
+macro_rules! impl_user_buf_arg {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl SyscallArg for $ty {
+                fn encode(self, args: &mut RawArgs) {
+                    args.push(self.addr);
+                    args.push(self.len);
+                }
+
+                fn decode(args: &mut RawArgs) -> Result<Self, Errno> {
+                    Ok(Self {
+                        addr: args.take()?,
+                        len: args.take()?,
+                    })
+                }
+            }
+
+            /// `0x1000010[64]`(address[length])
+            impl core::fmt::Debug for $ty {
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    write!(f, "{:#x}[{}]", self.addr, self.len)
+                }
+            }
+        )+
+    };
+}
+impl_user_buf_arg!(UserBuf, UserBufMut);
+
+/// NUL terminated user string, passed in 1 register.
+///
+/// For the kernel it's just an address. Copy it by `uaccess::copy_str_from_user`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct UserCStr {
+    pub addr: usize,
+}
+
+impl From<&core::ffi::CStr> for UserCStr {
+    fn from(s: &core::ffi::CStr) -> Self {
+        Self {
+            addr: s.as_ptr() as usize,
+        }
+    }
+}
+
+impl SyscallArg for UserCStr {
+    fn encode(self, args: &mut RawArgs) {
+        args.push(self.addr);
+    }
+
+    fn decode(args: &mut RawArgs) -> Result<Self, Errno> {
+        Ok(Self { addr: args.take()? })
+    }
+}
+
+impl core::fmt::Debug for UserCStr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:#x}", self.addr)
+    }
+}
@@ -0,0 +1,94 @@
+//! Register calling convention of `ecall`.
+//!
+//! |                | standard   | legacy(`legacy-abi` feature) |
+//! |----------------|------------|------------------------------|
+//! | syscall number | a7         | a3                           |
+//! | arguments      | a0..=a5    | a0..=a2                      |
+//! | return value   | a0, a1     | a0                           |
+//!
+//! On failure, a0 is `-(errno)`.
+//!
+//! The standard one is the same as the RISC-V Linux convention.
+//! The legacy one is only for binaries built before the change. (Other registers are preserved.)
+use crate::arg::{RawArgs, RawRet};
+use crate::errno::Errno;
+
+/// Number of argument registers
+#[cfg(not(feature = "legacy-abi"))]
+pub const ARG_REGS: usize = 6;
+/// Number of argument registers
+#[cfg(feature = "legacy-abi")]
+pub const ARG_REGS: usize = 3;
+
+/// Number of return registers
+#[cfg(not(feature = "legacy-abi"))]
+pub const RET_REGS: usize = 2;
+/// Number of return registers
+#[cfg(feature = "legacy-abi")]
+pub const RET_REGS: usize = 1;
+
+/// a0..=a7 at `ecall` -> (syscall number, arguments)
+pub fn decode_call(a: &[usize; 8]) -> (usize, RawArgs) {
+    #[cfg(not(feature = "legacy-abi"))]
+    let (sysno, args) = (a[7], [a[0], a[1], a[2], a[3], a[4], a[5]]);
+    #[cfg(feature = "legacy-abi")]
+    let (sysno, args) = (a[3], [a[0], a[1], a[2]]);
+    (sysno, RawArgs::new(args))
+}
+
+/// Result -> return registers. (Write back from a0 in order.)
+pub fn encode_ret(result: Result<RawRet, Errno>) -> RawRet {
+    match result {
+        Ok(ret) => ret,
+        Err(errno) => {
+            let mut ret = RawRet::default();
+            ret[0] = -(errno as isize) as usize;
+            ret
+        }
+    }
+}
+
+/// Return registers -> Result
+pub fn decode_ret(ret: RawRet) -> Result<RawRet, Errno> {
+    Errno::from_ret(ret[0] as isize).map(|_| ret)
+}
+
+/// Issue `ecall` with this convention.
+///
+/// # Safety
+/// The arguments must be valid for the system call `sysno`.
+#[cfg(feature = "user")]
+#[inline(always)]
+pub unsafe fn ecall(sysno: usize, args: &RawArgs) -> RawRet {
+    #[cfg(not(feature = "legacy-abi"))]
+    {
+        let [arg0, arg1, arg2, arg3, arg4, arg5] = *args.regs();
+        let ret0;
+        let ret1;
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") arg0 => ret0,
+            inlateout("a1") arg1 => ret1,
+            in("a2") arg2,
+            in("a3") arg3,
+            in("a4") arg4,
+            in("a5") arg5,
+            in("a7") sysno,
+        );
+        [ret0, ret1]
+    }
+    #[cfg(feature = "legacy-abi")]
+    {
+        let [arg0, arg1, arg2] = *args.regs();
+        let ret0;
+        // x10: a0, x11: a1, x12: a2 -> x10: system call result
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") arg0 => ret0,
+            in("a1") arg1,
+            in("a2") arg2,
+            in("a3") sysno,
+        );
+        [ret0]
+    }
+}
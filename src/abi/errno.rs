@@ -0,0 +1,100 @@
+//! Error numbers shared by the kernel & user programs.
+//!
+//! A failed system call returns `-(errno)` in `a0`. The numbers follow Linux(asm-generic/errno-base.h).
+use core::fmt;
+
+macro_rules! define_errno {
+    ($($(#[$attr:meta])* $name:ident = $num:literal, $desc:literal;)+) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[repr(isize)]
+        pub enum Errno {
+            $($(#[$attr])* $name = $num,)+
+        }
+
+        impl Errno {
+            /// errno number -> Errno
+            pub fn from_num(num: isize) -> Option<Self> {
+                match num {
+                    $($num => Some(Self::$name),)+
+                    _ => None,
+                }
+            }
+
+            /// Human readable message (e.g. `"No such file or directory"`)
+            pub fn description(&self) -> &'static str {
+                match self {
+                    $(Self::$name => $desc,)+
+                }
+            }
+        }
+    };
+}
+
+define_errno! {
+    /// Operation not permitted
+    EPERM = 1, "Operation not permitted";
+    /// No such file or directory
+    ENOENT = 2, "No such file or directory";
+    /// No such process
+    ESRCH = 3, "No such process";
+    /// I/O error
+    EIO = 5, "I/O error";
+    /// Exec format error
+    ENOEXEC = 8, "Exec format error";
+    /// Bad file number
+    EBADF = 9, "Bad file number";
+    /// Try again
+    EAGAIN = 11, "Try again";
+    /// Out of memory
+    ENOMEM = 12, "Out of memory";
+    /// Bad address
+    EFAULT = 14, "Bad address";
+    /// File exists
+    EEXIST = 17, "File exists";
+    /// Cross-device link
+    EXDEV = 18, "Cross-device link";
+    /// No such device
+    ENODEV = 19, "No such device";
+    /// Not a directory
+    ENOTDIR = 20, "Not a directory";
+    /// Is a directory
+    EISDIR = 21, "Is a directory";
+    /// Invalid argument
+    EINVAL = 22, "Invalid argument";
+    /// Too many open files
+    EMFILE = 24, "Too many open files";
+    /// Not a typewriter
+    ENOTTY = 25, "Not a typewriter";
+    /// File too large
+    EFBIG = 27, "File too large";
+    /// No space left on device
+    ENOSPC = 28, "No space left on device";
+    /// Illegal seek
+    ESPIPE = 29, "Illegal seek";
+    /// Read-only file system
+    EROFS = 30, "Read-only file system";
+    /// File name too long
+    ENAMETOOLONG = 36, "File name too long";
+    /// Function not implemented
+    ENOSYS = 38, "Function not implemented";
+    /// Directory not empty
+    ENOTEMPTY = 39, "Directory not empty";
+}
+
+impl Errno {
+    /// Syscall return register value -> Result
+    ///
+    /// `-4095..=-1` is an error, everything else is a success value.
+    pub fn from_ret(ret: isize) -> Result<usize, Self> {
+        match ret {
+            -4095..=-1 => Err(Self::from_num(-ret).unwrap_or(Self::EINVAL)),
+            _ => Ok(ret as usize),
+        }
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self, self.description())
+    }
+}
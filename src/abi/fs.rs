@@ -0,0 +1,151 @@
+//! File types shared by the kernel & user programs: `open` flags, `lseek` whence, `fstat` &
+//! `readdir` results.
+//!
+//! The values follow Linux(asm-generic/fcntl.h), so the Linux personality can pass them through.
+//!
+//! ```ignore
+//! let fd = sys::open(path, OpenFlags::WRONLY | OpenFlags::CREAT | OpenFlags::TRUNC)?;
+//! sys::write(fd, b"hello\n"[..].into())?;
+//! sys::close(fd)?;
+//! ```
+use crate::arg::{RawArgs, SyscallArg};
+use crate::errno::Errno;
+use core::{fmt, ops};
+
+/// `open` flags
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(u32);
+
+impl OpenFlags {
+    /// Read only access (no bits set)
+    pub const RDONLY: Self = Self(0);
+    /// Write only access
+    pub const WRONLY: Self = Self(1);
+    /// Read & write access
+    pub const RDWR: Self = Self(2);
+    /// Create the file if not exists.
+    pub const CREAT: Self = Self(0o100);
+    /// Truncate the file to 0 bytes. (needs the write access)
+    pub const TRUNC: Self = Self(0o1000);
+    /// Every write appends to the end of the file.
+    pub const APPEND: Self = Self(0o2000);
+
+    /// Mask of the access mode
+    const ACCMODE: u32 = 3;
+    const ALL: Self = Self(Self::ACCMODE | Self::CREAT.0 | Self::TRUNC.0 | Self::APPEND.0);
+
+    /// Unknown bits are ignored.
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn readable(self) -> bool {
+        matches!(self.0 & Self::ACCMODE, 0 | 2)
+    }
+
+    pub const fn writable(self) -> bool {
+        matches!(self.0 & Self::ACCMODE, 1 | 2)
+    }
+}
+
+impl ops::BitOr for OpenFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Debug for OpenFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = match self.0 & Self::ACCMODE {
+            0 => "RDONLY",
+            1 => "WRONLY",
+            _ => "RDWR",
+        };
+        write!(f, "OpenFlags({}", access)?;
+        let names = [
+            (Self::CREAT, "CREAT"),
+            (Self::TRUNC, "TRUNC"),
+            (Self::APPEND, "APPEND"),
+        ];
+        for (flag, name) in names {
+            if self.contains(flag) {
+                write!(f, " | {}", name)?;
+            }
+        }
+        f.write_str(")")
+    }
+}
+
+impl SyscallArg for OpenFlags {
+    fn encode(self, args: &mut RawArgs) {
+        args.push(self.0 as usize);
+    }
+
+    fn decode(args: &mut RawArgs) -> Result<Self, Errno> {
+        Ok(Self::from_bits(args.take()? as u32))
+    }
+}
+
+/// `lseek` whence: from the start of the file
+pub const SEEK_SET: u32 = 0;
+/// `lseek` whence: from the current offset
+pub const SEEK_CUR: u32 = 1;
+/// `lseek` whence: from the end of the file
+pub const SEEK_END: u32 = 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum FileKind {
+    #[default]
+    Regular = 1,
+    Directory = 2,
+    /// e.g. the console
+    CharDevice = 3,
+}
+
+/// Written by `fstat`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct Stat {
+    pub kind: FileKind,
+    /// Bytes (0 for devices)
+    pub size: usize,
+}
+
+/// Max bytes of a file name
+pub const NAME_MAX: usize = 255;
+
+/// One directory entry, written by `readdir`
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Dirent {
+    pub kind: FileKind,
+    pub name_len: usize,
+    pub name: [u8; NAME_MAX],
+}
+
+impl Dirent {
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len.min(NAME_MAX)]).unwrap_or("?")
+    }
+}
+
+impl Default for Dirent {
+    fn default() -> Self {
+        Self {
+            kind: FileKind::default(),
+            name_len: 0,
+            name: [0; NAME_MAX],
+        }
+    }
+}
@@ -0,0 +1,21 @@
+//! System call ABI shared by the kernel & user programs.
+//!
+//! Each system call is defined once in [`syscalls`]. From that single definition we generate
+//! - syscall numbers(`SYS_*`)
+//! - [`Syscalls`]: the signatures the kernel must implement
+//! - [`syscall_table`]: the kernel dispatch table(typed argument decoding included)
+//! - `user::*`: safe `Result` returning wrappers for user programs(`user` feature)
+//!
+//! [`symbols`] is the symbol table format embedded in both images for backtraces.
+#![no_std]
+pub mod arg;
+pub mod conv;
+pub mod errno;
+pub mod fs;
+pub mod symbols;
+pub mod syscalls;
+pub mod tty;
+
+pub use arg::{RawArgs, RawRet, SyscallArg, SyscallRet, UserBuf, UserBufMut, UserCStr};
+pub use errno::Errno;
+pub use syscalls::*;
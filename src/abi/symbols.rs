@@ -0,0 +1,110 @@
+//! Symbol table embedded in the image at build time. (Used to symbolize backtraces)
+//!
+//! Binary format(little endian, generated by `build.rs`):
+//!
+//! | field   | type                 | description                               |
+//! |---------|----------------------|-------------------------------------------|
+//! | magic   | `[u8; 4]`            | `b"SYMS"`                                 |
+//! | count   | `u32`                |                                           |
+//! | entries | `[SymbolEntry; count]` | sorted by `addr`                        |
+//! | names   | `[u8]`               | UTF-8 demangled names(no NUL terminator)  |
+
+pub const SYMBOLS_MAGIC: [u8; 4] = *b"SYMS";
+
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct SymbolEntry {
+    addr: u32,
+    size: u32,
+    /// offset in `names`
+    name_offset: u32,
+    name_len: u32,
+}
+
+/// Resolved symbol of an address.
+#[derive(Clone, Copy, Debug)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    /// Offset from the symbol start
+    pub offset: usize,
+}
+
+pub struct SymbolTable<'a> {
+    entries: &'a [u8],
+    count: usize,
+    names: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    /// # Return
+    /// `None` if `data` is not a symbol table(e.g. not generated).
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.get(..4)? != SYMBOLS_MAGIC {
+            return None;
+        }
+        let count = u32::from_le_bytes(data.get(4..8)?.try_into().ok()?) as usize;
+        let names_start = 8 + count * core::mem::size_of::<SymbolEntry>();
+        Some(Self {
+            entries: data.get(8..names_start)?,
+            count,
+            names: &data[names_start..],
+        })
+    }
+
+    /// Parse the table at `ptr` whose end is not known. (e.g. appended to a flat binary)
+    ///
+    /// # Safety
+    /// `ptr` must be readable for 8 bytes, and for the whole table if it starts with [`SYMBOLS_MAGIC`].
+    pub unsafe fn from_ptr(ptr: *const u8) -> Option<Self> {
+        let header = core::slice::from_raw_parts(ptr, 8);
+        if header[..4] != SYMBOLS_MAGIC {
+            return None;
+        }
+        let count = u32::from_le_bytes(header[4..].try_into().ok()?) as usize;
+        let entries_len = count * core::mem::size_of::<SymbolEntry>();
+        // The names are stored in the entry order, so the last name ends the table.
+        let mut table = Self::parse(core::slice::from_raw_parts(ptr, 8 + entries_len))?;
+        let names_len = match count {
+            0 => 0,
+            _ => {
+                let last = table.entry(count - 1);
+                (last.name_offset + last.name_len) as usize
+            }
+        };
+        table.names = core::slice::from_raw_parts(ptr.add(8 + entries_len), names_len);
+        Some(table)
+    }
+
+    fn entry(&self, idx: usize) -> SymbolEntry {
+        let offset = idx * core::mem::size_of::<SymbolEntry>();
+        unsafe { (self.entries.as_ptr().add(offset) as *const SymbolEntry).read_unaligned() }
+    }
+
+    /// Find the symbol that contains `addr`.
+    pub fn lookup(&self, addr: usize) -> Option<Symbol<'a>> {
+        // binary search: the last entry with `entry.addr <= addr`
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.entry(mid).addr as usize <= addr {
+                true => low = mid + 1,
+                false => high = mid,
+            }
+        }
+        let entry = self.entry(low.checked_sub(1)?);
+        let offset = addr - entry.addr as usize;
+        // Size 0 symbols(e.g. from asm) cover until the next symbol.
+        if entry.size != 0 && offset >= entry.size as usize {
+            return None;
+        }
+
+        let name_start = entry.name_offset as usize;
+        let name = self
+            .names
+            .get(name_start..name_start + entry.name_len as usize)?;
+        Some(Symbol {
+            name: core::str::from_utf8(name).ok()?,
+            offset,
+        })
+    }
+}
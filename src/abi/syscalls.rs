@@ -0,0 +1,241 @@
+//! The system call list. **Add new system calls only here.**
+//!
+//! See [`crate::conv`] for the register convention.
+use crate::arg::{RawArgs, RawRet, SyscallArg, SyscallRet, UserBuf, UserBufMut, UserCStr};
+use crate::errno::Errno;
+use crate::fs::OpenFlags;
+use core::fmt;
+
+/// Kernel side system call implementation. (typed arguments are already decoded)
+pub type RawSyscallFn = fn(RawArgs) -> Result<RawRet, Errno>;
+
+/// One entry of the kernel dispatch table.
+#[derive(Clone, Copy)]
+pub struct SyscallDesc {
+    pub sysno: usize,
+    pub name: &'static str,
+    pub handler: RawSyscallFn,
+}
+
+macro_rules! define_syscalls {
+    ($(
+        $(#[$attr:meta])*
+        $sysno_name:ident = $sysno:literal => fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty;
+    )+) => {
+        $(
+            #[doc = concat!("System call number of [`Syscalls::", stringify!($name), "`]")]
+            pub const $sysno_name: usize = $sysno;
+        )+
+
+        /// Number of defined system calls
+        pub const SYSCALL_COUNT: usize = [$(stringify!($name)),+].len();
+
+        /// Signatures of all system calls. The kernel implements this.
+        pub trait Syscalls {
+            $(
+                $(#[$attr])*
+                fn $name($($arg: $ty),*) -> Result<$ret, Errno>;
+            )+
+        }
+
+        /// Build the dispatch table from the [`Syscalls`] implementation `S`.
+        pub fn syscall_table<S: Syscalls>() -> [SyscallDesc; SYSCALL_COUNT] {
+            [$(
+                SyscallDesc {
+                    sysno: $sysno,
+                    name: stringify!($name),
+                    handler: |mut _args: RawArgs| {
+                        $(let $arg = <$ty as SyscallArg>::decode(&mut _args)?;)*
+                        S::$name($($arg),*).map(SyscallRet::into_ret)
+                    },
+                },
+            )+]
+        }
+
+        /// System call number -> name
+        pub fn syscall_name(sysno: usize) -> Option<&'static str> {
+            match sysno {
+                $($sysno => Some(stringify!($name)),)+
+                _ => None,
+            }
+        }
+
+        /// Write the decoded arguments of `sysno` like `fd=1, len=5`. (For tracing)
+        ///
+        /// Undecodable arguments are written as `?`. Unknown syscalls are written as raw registers.
+        pub fn fmt_syscall_args(sysno: usize, mut _args: RawArgs, out: &mut dyn fmt::Write) -> fmt::Result {
+            match sysno {
+                $($sysno => {
+                    let mut _sep = "";
+                    $(
+                        write!(out, "{}{}=", _sep, stringify!($arg))?;
+                        match <$ty as SyscallArg>::decode(&mut _args) {
+                            Ok(value) => write!(out, "{:?}", value)?,
+                            Err(_) => out.write_str("?")?,
+                        }
+                        _sep = ", ";
+                    )*
+                    Ok(())
+                })+
+                _ => write!(out, "{:x?}", _args.regs()),
+            }
+        }
+
+        /// Safe wrappers of all system calls for user programs.
+        #[cfg(feature = "user")]
+        pub mod user {
+            use super::*;
+
+            $(
+                $(#[$attr])*
+                #[inline]
+                pub fn $name($($arg: $ty),*) -> Result<$ret, Errno> {
+                    #[allow(unused_mut)]
+                    let mut args = RawArgs::default();
+                    $(<$ty as SyscallArg>::encode($arg, &mut args);)*
+                    let ret = unsafe { crate::conv::ecall($sysno_name, &args) };
+                    crate::conv::decode_ret(ret).map(<$ret as SyscallRet>::from_ret)
+                }
+            )+
+        }
+    };
+}
+
+define_syscalls! {
+    /// Write one character to the console.
+    SYS_PUTCHAR = 1 => fn put_char(ch: char) -> ();
+    /// Read one byte from the console TTY. If there is no input, sleep until it arrives.
+    ///
+    /// In the canonical mode(default), the input is available after the line is completed.
+    /// Return EOT(Ctrl-D, `0x04`) at the end of file.
+    SYS_GETCHAR = 2 => fn get_char() -> u8;
+    /// Terminate the calling process. Never returns on success.
+    SYS_EXIT = 3 => fn exit(status: i32) -> ();
+    /// Enable/disable the syscall tracing(strace) of `pid`. (`pid == 0`: the calling process)
+    SYS_TRACE = 4 => fn trace(pid: usize, enable: bool) -> ();
+    /// Power off the machine. Never returns on success.
+    SYS_SHUTDOWN = 5 => fn shutdown() -> ();
+    /// Reboot the machine. Never returns on success.
+    SYS_REBOOT = 6 => fn reboot() -> ();
+    /// Exit QEMU with `code`(0: pass, others: fail) to report a test result to the host.
+    /// Never returns on success.
+    SYS_EXIT_QEMU = 7 => fn exit_qemu(code: u16) -> ();
+    /// Read the kernel log(dmesg). If `buf` is smaller than the log, the newest part is read.
+    ///
+    /// Return the read bytes.
+    SYS_DMESG = 8 => fn dmesg(buf: UserBufMut) -> usize;
+    /// Device control. Only the console TTY is supported. (see [`crate::tty`])
+    ///
+    /// # Errors
+    /// - Not opened `fd`: EBADF
+    /// - Not a TTY: ENOTTY
+    /// - Unknown `request`: EINVAL
+    SYS_IOCTL = 9 => fn ioctl(fd: usize, request: usize, arg: usize) -> usize;
+    /// Write `buf` to `fd` at the file offset. Return the written bytes.
+    ///
+    /// # Errors
+    /// - Not opened for writing: EBADF
+    /// - `buf` is not readable: EFAULT
+    /// - Errors of the file system (e.g. ENOSPC, EFBIG, EIO)
+    SYS_WRITE = 10 => fn write(fd: usize, buf: UserBuf) -> usize;
+    /// Read from `fd` to `buf` at the file offset. On the console, if there is no input,
+    /// sleep until it arrives.
+    ///
+    /// Return the read bytes. (0: end of file)
+    /// In the canonical mode(default) of the TTY, up to one line is read.
+    ///
+    /// # Errors
+    /// - Not opened for reading: EBADF
+    /// - Directory: EISDIR
+    /// - `buf` is not writable: EFAULT
+    SYS_READ = 11 => fn read(fd: usize, buf: UserBufMut) -> usize;
+    /// Read the file `path` to `buf`. If `buf` is smaller than the file, the head is read.
+    ///
+    /// Return the read bytes.
+    ///
+    /// # Errors
+    /// Same as `open` & `read`
+    SYS_READFILE = 12 => fn readfile(path: UserCStr, buf: UserBufMut) -> usize;
+    /// Replace the content of the file `path` with `buf`. (Created if not exists)
+    ///
+    /// Return the written bytes.
+    ///
+    /// # Errors
+    /// Same as `open` & `write`
+    SYS_WRITEFILE = 13 => fn writefile(path: UserCStr, buf: UserBuf) -> usize;
+    /// Open the file `path` and return the lowest free fd. The offset starts from 0.
+    ///
+    /// There is no current directory. A relative path is resolved from the root.
+    ///
+    /// # Errors
+    /// - No such file (without `CREAT`): ENOENT
+    /// - A directory is opened for writing: EISDIR
+    /// - No free fd: EMFILE
+    /// - Nothing is mounted: ENOENT
+    /// - Errors of the file system (e.g. EROFS, ENOSPC)
+    SYS_OPEN = 14 => fn open(path: UserCStr, flags: OpenFlags) -> usize;
+    /// Close `fd`.
+    ///
+    /// # Errors
+    /// Not opened: EBADF
+    SYS_CLOSE = 15 => fn close(fd: usize) -> ();
+    /// Move the offset of `fd` by `whence`(`SEEK_*`) & `offset`. Return the new offset.
+    ///
+    /// The offset can be beyond the end. A write there fills the gap with 0.
+    ///
+    /// # Errors
+    /// - Not opened: EBADF
+    /// - Device: ESPIPE
+    /// - Negative result or unknown `whence`: EINVAL
+    SYS_LSEEK = 16 => fn lseek(fd: usize, offset: isize, whence: u32) -> usize;
+    /// Write the [`Stat`](crate::fs::Stat) of `fd` to `stat`. (`stat.len` must be its size)
+    ///
+    /// # Errors
+    /// - Not opened: EBADF
+    /// - Wrong `stat.len`: EINVAL
+    /// - `stat` is not writable: EFAULT
+    SYS_FSTAT = 17 => fn fstat(fd: usize, stat: UserBufMut) -> ();
+    /// Create the empty directory `path`.
+    ///
+    /// # Errors
+    /// - Already exists: EEXIST
+    /// - The parent doesn't exist: ENOENT
+    /// - Not supported by the file system: EPERM, EROFS
+    SYS_MKDIR = 18 => fn mkdir(path: UserCStr) -> ();
+    /// Remove the file `path`. The data is freed after the last fd of it is closed.
+    ///
+    /// # Errors
+    /// - No such file: ENOENT
+    /// - Directory: EISDIR
+    /// - Not supported by the file system: EPERM, EROFS
+    SYS_UNLINK = 19 => fn unlink(path: UserCStr) -> ();
+    /// Remove the empty directory `path`.
+    ///
+    /// # Errors
+    /// - No such directory: ENOENT
+    /// - Not a directory: ENOTDIR
+    /// - Not empty: ENOTEMPTY
+    /// - Not supported by the file system: EPERM, EROFS
+    SYS_RMDIR = 20 => fn rmdir(path: UserCStr) -> ();
+    /// Move `old` to `new`. The existing `new` is replaced, if it is the same kind.
+    /// (A directory is replaced only if it is empty.)
+    ///
+    /// # Errors
+    /// - No such file: ENOENT
+    /// - Another file system: EXDEV
+    /// - `new` is under `old`: EINVAL
+    /// - Different kind: EISDIR, ENOTDIR
+    /// - Not empty `new` directory: ENOTEMPTY
+    SYS_RENAME = 21 => fn rename(old: UserCStr, new: UserCStr) -> ();
+    /// Write the next entry of the directory `fd` to `dirent`(a [`Dirent`](crate::fs::Dirent)).
+    /// (`dirent.len` must be its size)
+    ///
+    /// Return 1 if an entry is read, 0 at the end of the directory.
+    ///
+    /// # Errors
+    /// - Not opened: EBADF
+    /// - Not a directory: ENOTDIR
+    /// - Wrong `dirent.len`: EINVAL
+    /// - `dirent` is not writable: EFAULT
+    SYS_READDIR = 22 => fn readdir(fd: usize, dirent: UserBufMut) -> usize;
+}
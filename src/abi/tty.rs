@@ -0,0 +1,83 @@
+//! Console TTY modes, switched by the `ioctl` system call on fd 0.
+//!
+//! ```ignore
+//! // raw mode: every byte is returned as soon as it arrives, without echo.
+//! let mode = TtyMode::from_bits(sys::ioctl(0, TTY_GET_MODE, 0)? as u32);
+//! let raw = mode.without(TtyMode::CANONICAL | TtyMode::ECHO);
+//! sys::ioctl(0, TTY_SET_MODE, raw.bits() as usize)?;
+//! ```
+use core::{fmt, ops};
+
+/// `ioctl` request: return the current [`TtyMode`] bits.
+pub const TTY_GET_MODE: usize = 1;
+/// `ioctl` request: set the [`TtyMode`] bits given by `arg`. Return 0.
+pub const TTY_SET_MODE: usize = 2;
+
+/// Line discipline flags (a subset of termios)
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TtyMode(u32);
+
+impl TtyMode {
+    /// Line editing: input is returned by lines. (`ICANON`)
+    ///
+    /// - backspace/DEL: erase a character
+    /// - Ctrl-U: erase the line
+    /// - Ctrl-D: return the line without newline. (On an empty line, end of file)
+    pub const CANONICAL: Self = Self(1 << 0);
+    /// Echo input characters. (`ECHO`)
+    pub const ECHO: Self = Self(1 << 1);
+    /// Translate input CR to NL. (`ICRNL`)
+    pub const ICRNL: Self = Self(1 << 2);
+    /// Translate output NL to CR NL. (`ONLCR`)
+    pub const ONLCR: Self = Self(1 << 3);
+
+    /// Like a terminal of Linux (cooked mode)
+    pub const DEFAULT: Self =
+        Self(Self::CANONICAL.0 | Self::ECHO.0 | Self::ICRNL.0 | Self::ONLCR.0);
+    const ALL: Self = Self::DEFAULT;
+
+    /// Unknown bits are ignored.
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl ops::BitOr for TtyMode {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Debug for TtyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (Self::CANONICAL, "CANONICAL"),
+            (Self::ECHO, "ECHO"),
+            (Self::ICRNL, "ICRNL"),
+            (Self::ONLCR, "ONLCR"),
+        ];
+        let mut sep = "";
+        f.write_str("TtyMode(")?;
+        for (flag, name) in names {
+            if self.contains(flag) {
+                write!(f, "{}{}", sep, name)?;
+                sep = " | ";
+            }
+        }
+        f.write_str(")")
+    }
+}
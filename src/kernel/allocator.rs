@@ -1,27 +1,135 @@
-use crate::pages::alloc_pages;
+//! Kernel heap: first fit free list allocator.
+//!
+//! The heap grows by pages from `alloc_pages` when no free block fits. The free blocks are kept
+//! sorted by address, and a freed block is merged with the adjacent free blocks.
+use crate::pages::{alloc_pages, PAGE_SIZE};
 use core::alloc::{GlobalAlloc, Layout};
+use core::mem::size_of;
+use core::ptr;
+use kernel::addr::align_up;
+use kernel::spinlock::SpinLock;
 
-struct BumpPtrAlloc {}
+/// Header written in the free block itself
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// Unit of the block addresses & sizes. Any free remainder of a block is a multiple of it, so
+/// it can hold the [`FreeBlock`] header, and an allocation is exactly the block freed later.
+const BLOCK_UNIT: usize = size_of::<FreeBlock>();
+
+struct FreeList {
+    /// The lowest free block
+    head: *mut FreeBlock,
+}
+
+/// Only accessed under the lock.
+unsafe impl Send for FreeList {}
+
+impl FreeList {
+    /// Size of the block actually used for `layout`
+    fn block_size(layout: &Layout) -> usize {
+        align_up(layout.size().max(1), BLOCK_UNIT)
+    }
 
-impl BumpPtrAlloc {
-    const fn empty() -> Self {
-        Self {}
+    /// Take the first fitting part of a free block.
+    unsafe fn take(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut block = self.head;
+        while !block.is_null() {
+            let block_start = block as usize;
+            let block_end = block_start + (*block).size;
+            let start = align_up(block_start, align);
+            let end = start + size;
+            if end <= block_end {
+                let next = (*block).next;
+                let tail = match end != block_end {
+                    true => {
+                        let tail = end as *mut FreeBlock;
+                        tail.write(FreeBlock {
+                            size: block_end - end,
+                            next,
+                        });
+                        tail
+                    }
+                    false => next,
+                };
+                if start != block_start {
+                    // Keep the front padding as the (shrunk) block.
+                    (*block).size = start - block_start;
+                    (*block).next = tail;
+                } else if prev.is_null() {
+                    self.head = tail;
+                } else {
+                    (*prev).next = tail;
+                }
+                return Some(start as *mut u8);
+            }
+            prev = block;
+            block = (*block).next;
+        }
+        None
+    }
+
+    /// Insert `[addr, addr + size)` in the address order & merge the neighbors.
+    unsafe fn insert(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let block = addr as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+        if !next.is_null() && addr + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == addr {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
     }
 }
 
-unsafe impl Sync for BumpPtrAlloc {}
+struct Heap {
+    free_list: SpinLock<FreeList>,
+}
 
-unsafe impl GlobalAlloc for BumpPtrAlloc {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let paddr: usize = alloc_pages(layout.size()).into();
-        paddr as *mut u8
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let size = FreeList::block_size(&layout);
+        let align = layout.align().max(BLOCK_UNIT);
+        let mut free_list = self.free_list.lock();
+        if let Some(ptr) = free_list.take(size, align) {
+            return ptr;
+        }
+        // Grow: enough for the worst alignment padding
+        let pages = align_up(size + align, PAGE_SIZE) / PAGE_SIZE;
+        let paddr: usize = alloc_pages(pages).into();
+        free_list.insert(paddr, pages * PAGE_SIZE);
+        free_list.take(size, align).unwrap_or_default()
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {}
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let size = FreeList::block_size(&layout);
+        self.free_list.lock().insert(ptr as usize, size);
+    }
 }
 
 #[global_allocator]
-static SIMPLE_ALLOCATOR: BumpPtrAlloc = BumpPtrAlloc::empty();
+static HEAP: Heap = Heap {
+    free_list: SpinLock::new(FreeList {
+        head: ptr::null_mut(),
+    }),
+};
+
 #[alloc_error_handler]
 fn hlt(_layout: Layout) -> ! {
     panic!("Failed to allocate heap")
@@ -0,0 +1,102 @@
+//! Kernel stack backtrace by walking the frame pointer chain.
+//!
+//! The kernel is built with `-C force-frame-pointers=yes`(.cargo/config.toml), so every frame is:
+//! ```txt
+//! fp     -> (caller's sp)
+//! fp - 4 -> return address
+//! fp - 8 -> caller's fp
+//! ```
+//! Return addresses are resolved with the symbol table embedded by `build.rs`.
+//! (Only with the 2 pass build `make build`/`make run`, otherwise printed as `??`.)
+//!
+//! Output example:
+//! ```txt
+//! backtrace:
+//!   #0 0x80201a2c kernel::pages::map_page+0x54
+//!   #1 0x80202f10 os::proc::Executer::push+0x1d8
+//! ```
+use crate::println;
+use abi::symbols::SymbolTable;
+use core::arch::asm;
+
+// Defined symbols by kernel.ld
+extern "C" {
+    fn __kernel_base();
+    fn __free_ram_end();
+    fn __ksyms();
+    fn __ksyms_end();
+}
+
+/// Stop walking here even if the chain looks valid. (e.g. broken stack)
+const MAX_DEPTH: usize = 32;
+
+const KSYMS_SIZE: usize = include_bytes!(concat!(env!("OUT_DIR"), "/ksyms.bin")).len();
+
+/// Generated by build.rs.
+///
+/// NOTE: Access via `__ksyms`..`__ksyms_end`, not the array length,
+/// so that the table size never changes the code between the 2 links.
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: [u8; KSYMS_SIZE] = *include_bytes!(concat!(env!("OUT_DIR"), "/ksyms.bin"));
+
+fn symbols() -> Option<SymbolTable<'static>> {
+    let start = __ksyms as usize;
+    let len = __ksyms_end as usize - start;
+    SymbolTable::parse(unsafe { core::slice::from_raw_parts(start as *const u8, len) })
+}
+
+/// # Parameters
+/// - is_return_addr: `pc` is the next of a `call`. (resolve the call itself, not the next one)
+fn print_frame(depth: usize, pc: usize, is_return_addr: bool) {
+    let lookup_addr = match is_return_addr {
+        true => pc - 1,
+        false => pc,
+    };
+    match symbols().and_then(|symbols| symbols.lookup(lookup_addr)) {
+        Some(symbol) => println!(
+            "  #{} {:#010x} {}+{:#x}",
+            depth,
+            pc,
+            symbol.name,
+            symbol.offset + (pc - lookup_addr)
+        ),
+        None => println!("  #{} {:#010x} ??", depth, pc),
+    }
+}
+
+/// Print the return addresses from the frame `fp`.
+fn walk(mut depth: usize, mut fp: usize) {
+    // All kernel stacks(boot stack, process kernel stacks) are in the kernel image or free RAM.
+    let stack_range = (__kernel_base as usize + 8)..=__free_ram_end as usize;
+    while depth < MAX_DEPTH && fp % 4 == 0 && stack_range.contains(&fp) {
+        let ra = unsafe { ((fp - 4) as *const usize).read() };
+        let prev_fp = unsafe { ((fp - 8) as *const usize).read() };
+        if ra == 0 {
+            break;
+        }
+        print_frame(depth, ra, true);
+        fp = prev_fp;
+        depth += 1;
+    }
+}
+
+/// Print the backtrace of the caller.
+#[inline(never)]
+pub fn print_backtrace() {
+    let fp: usize;
+    unsafe { asm!("mv {}, fp", out(reg) fp) };
+    println!("backtrace:");
+    walk(0, fp);
+}
+
+/// Print the backtrace of an interrupted S-Mode context.
+///
+/// # Parameters
+/// - pc: sepc
+/// - fp: s0 at the trap
+pub fn print_backtrace_from(pc: usize, fp: usize) {
+    println!("backtrace:");
+    print_frame(0, pc, false);
+    walk(1, fp);
+}
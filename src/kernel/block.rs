@@ -0,0 +1,46 @@
+//! Block devices (the disk for the file systems).
+use core::fmt;
+use kernel::spinlock::SpinLock;
+
+/// Bytes per sector
+pub const SECTOR_SIZE: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockError {
+    /// Beyond the last sector
+    OutOfRange(u64),
+    /// Write to a read only device
+    ReadOnly,
+    /// The device reported an error.
+    Io,
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfRange(sector) => write!(f, "sector {} is out of range", sector),
+            Self::ReadOnly => write!(f, "read only device"),
+            Self::Io => write!(f, "I/O error"),
+        }
+    }
+}
+
+/// Sector addressed storage
+pub trait BlockDevice: Sync {
+    /// Number of sectors
+    fn sector_count(&self) -> u64;
+    fn read_sector(&self, sector: u64, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), BlockError>;
+    fn write_sector(&self, sector: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), BlockError>;
+}
+
+static ROOT_DEVICE: SpinLock<Option<&'static dyn BlockDevice>> = SpinLock::new(None);
+
+/// Use `device` as the root disk. (Replaces the previous one.)
+pub fn register_root_device(device: &'static dyn BlockDevice) {
+    *ROOT_DEVICE.lock() = Some(device);
+}
+
+/// `None` if no disk is attached.
+pub fn root_device() -> Option<&'static dyn BlockDevice> {
+    *ROOT_DEVICE.lock()
+}
@@ -1,23 +1,142 @@
-use crate::sbi::sbi_call;
+//! Kernel console.
+//!
+//! The console is byte oriented: text is written as UTF-8 bytes.
+//!
+//! The backend is selected by cargo feature:
+//! - default: SBI debug console (legacy putchar/getchar if DBCN is not available)
+//! - `uart-console`: NS16550A UART driver (no M-Mode round trip per byte)
+//!
+//! Input is interrupt driven in both cases: the UART receive interrupt passes the bytes to
+//! the TTY(see [`crate::tty`]) and wakes the processes sleeping on [`WaitChannel::ConsoleInput`].
+use crate::drivers::{plic, uart};
+use crate::proc::{wake_up, WaitChannel};
+use crate::sbi::dbcn;
+use crate::tty;
 use core::fmt::{self, Write};
+use kernel::spinlock::{SpinLock, SpinLockGuard};
 
-pub fn put_char(ch: usize) {
-    sbi_call([ch, 0, 0, 0, 0, 0, 0, 1]);
+/// Byte I/O device of the console
+pub trait ConsoleBackend: Sync {
+    /// Called once before the first output.
+    fn init(&self) {}
+    /// Block until the byte is sent.
+    fn put_byte(&self, byte: u8);
+    /// Block until all bytes are sent.
+    fn put_bytes(&self, bytes: &[u8]) {
+        for byte in bytes {
+            self.put_byte(*byte);
+        }
+    }
+    /// Non blocking. `None` if no input.
+    fn get_byte(&self) -> Option<u8>;
 }
 
-struct Stdout;
+/// Console via SBI ecalls
+pub struct SbiConsole;
 
-impl core::fmt::Write for Stdout {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for c in s.chars() {
-            put_char(c as usize);
+impl ConsoleBackend for SbiConsole {
+    fn put_byte(&self, byte: u8) {
+        // Writing to the debug console can't fail.
+        let _ = dbcn::console_write_byte(byte);
+    }
+
+    /// One ecall for all bytes if DBCN is available.
+    fn put_bytes(&self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            match dbcn::console_write(bytes) {
+                Ok(written) if written > 0 => bytes = &bytes[written.min(bytes.len())..],
+                _ => {
+                    bytes.iter().for_each(|byte| self.put_byte(*byte));
+                    return;
+                }
+            }
         }
+    }
+
+    fn get_byte(&self) -> Option<u8> {
+        let mut byte = [0];
+        match dbcn::console_read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(feature = "uart-console"))]
+static BACKEND: &dyn ConsoleBackend = &SbiConsole;
+#[cfg(feature = "uart-console")]
+static BACKEND: &dyn ConsoleBackend = &uart::UART;
+
+pub fn backend() -> &'static dyn ConsoleBackend {
+    BACKEND
+}
+
+pub fn init() {
+    backend().init();
+}
+
+/// Enable the interrupt driven input. (after `plic::init`)
+pub fn init_input() {
+    plic::register_irq_handler(uart::IRQ, 1, handle_uart_irq);
+    uart::UART.enable_rx_interrupt();
+}
+
+fn handle_uart_irq() {
+    // On QEMU `virt`, the SBI console is this UART too.
+    let mut readable = false;
+    while let Some(byte) = backend().get_byte() {
+        readable = tty::receive(byte);
+    }
+    if readable {
+        wake_up(WaitChannel::ConsoleInput);
+    }
+}
+
+/// Serializes the output, so each `print`/`write` comes out at once.
+static LOCK: SpinLock<()> = SpinLock::new(());
+
+/// The locked console. (Interrupts are disabled while held.)
+pub struct Console {
+    _guard: SpinLockGuard<'static, ()>,
+}
+
+impl Console {
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        backend().put_bytes(bytes);
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
         Ok(())
     }
 }
 
+/// Take the console for a series of writes.
+///
+/// Don't print by others(e.g. `println!`) while holding it. It deadlocks.
+pub fn lock() -> Console {
+    Console {
+        _guard: LOCK.lock(),
+    }
+}
+
+/// Release the console lock taken by anyone, so that the panic message gets out.
+///
+/// # Safety
+/// Only for the panic(& fatal trap) path. The interrupted output is garbled.
+pub unsafe fn force_unlock() {
+    LOCK.force_unlock();
+}
+
+/// Write `ch` encoded in UTF-8.
+pub fn put_char(ch: char) {
+    lock().write_bytes(ch.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
 pub fn print(args: fmt::Arguments) {
-    Stdout.write_fmt(args).unwrap();
+    lock().write_fmt(args).unwrap();
 }
 
 #[macro_export]
@@ -33,8 +152,3 @@ macro_rules! println {
         $crate::console::print(format_args!(concat!($fmt, "\n") $(, $($arg)+)?))
     }
 }
-
-pub fn get_char() -> isize {
-    let ret = sbi_call([0, 0, 0, 0, 0, 0, 0, 2]);
-    ret.error
-}
@@ -0,0 +1,29 @@
+//! Device drivers of the QEMU `virt` machine.
+//!
+//! The MMIO registers are accessed with the physical address,
+//! so every region here is identity mapped in all process page tables. (see `pages::ident_map_in_kernel`)
+pub mod plic;
+pub mod test_finisher;
+pub mod uart;
+pub mod virtio;
+pub mod virtio_blk;
+
+use crate::pages::PAGE_SIZE;
+use kernel::addr::{align_down, align_up};
+
+/// (physical base address, size) of the MMIO regions
+pub const MMIO_REGIONS: &[(usize, usize)] = &[
+    (plic::BASE, plic::SIZE),
+    (test_finisher::BASE, test_finisher::SIZE),
+    (uart::BASE, uart::SIZE),
+    (virtio::BASE, virtio::SIZE),
+];
+
+/// Does `[start, end)` overlap a page of the MMIO regions?
+pub fn overlaps_mmio(start: usize, end: usize) -> bool {
+    MMIO_REGIONS.iter().any(|(base, size)| {
+        let page_start = align_down(*base, PAGE_SIZE);
+        let page_end = align_up(base + size, PAGE_SIZE);
+        start < page_end && page_start < end
+    })
+}
@@ -0,0 +1,83 @@
+//! PLIC(Platform-Level Interrupt Controller) of QEMU `virt`, for the S-Mode context of hart 0.
+//!
+//! Device interrupts(IRQ) arrive as the supervisor external interrupt.
+//! The handler claims the IRQ, calls the registered IRQ handler and completes it.
+//! - ref: https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc
+use crate::interrupt::register_interrupt_handler;
+use crate::trap::TrapFrame;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kernel::riscv::scause::Interrupt;
+
+pub const BASE: usize = 0x0c00_0000;
+pub const SIZE: usize = 0x40_0000;
+
+/// hart 0 S-Mode (context 0 is hart 0 M-Mode)
+const CONTEXT: usize = 1;
+const PRIORITY: usize = BASE;
+const ENABLE: usize = BASE + 0x2000 + 0x80 * CONTEXT;
+const THRESHOLD: usize = BASE + 0x20_0000 + 0x1000 * CONTEXT;
+const CLAIM_COMPLETE: usize = THRESHOLD + 4;
+
+/// Max IRQ number + 1 of QEMU `virt`
+const IRQ_LEN: usize = 128;
+
+/// Called for the claimed IRQ.
+pub type IrqHandler = fn();
+
+/// Registered handler ptr per IRQ. (0 == not registered)
+static IRQ_HANDLERS: [AtomicUsize; IRQ_LEN] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const UNREGISTERED: AtomicUsize = AtomicUsize::new(0);
+    [UNREGISTERED; IRQ_LEN]
+};
+
+fn write_reg(addr: usize, value: u32) {
+    unsafe { (addr as *mut u32).write_volatile(value) }
+}
+
+fn read_reg(addr: usize) -> u32 {
+    unsafe { (addr as *const u32).read_volatile() }
+}
+
+/// Accept all priorities & hook the supervisor external interrupt.
+pub fn init() {
+    write_reg(THRESHOLD, 0);
+    register_interrupt_handler(Interrupt::SupervisorExternal, handle_external_interrupt);
+}
+
+/// Hook `handler` to `irq` & enable it with `priority`(1..=7).
+///
+/// # Panics
+/// `irq` is out of range.
+pub fn register_irq_handler(irq: usize, priority: u32, handler: IrqHandler) {
+    assert!(0 < irq && irq < IRQ_LEN, "invalid irq {irq}");
+    IRQ_HANDLERS[irq].store(handler as usize, Ordering::Release);
+    write_reg(PRIORITY + 4 * irq, priority);
+    let enable = ENABLE + 4 * (irq / 32);
+    write_reg(enable, read_reg(enable) | 1 << (irq % 32));
+}
+
+/// Handle all pending IRQs.
+///
+/// Also called by the idle loop, where the interrupt is pending but not trapped.
+pub fn handle_irqs() {
+    loop {
+        let irq = read_reg(CLAIM_COMPLETE) as usize;
+        if irq == 0 {
+            break;
+        }
+        let handler_ptr = IRQ_HANDLERS
+            .get(irq)
+            .map_or(0, |h| h.load(Ordering::Acquire));
+        // An unknown IRQ is just completed. (It was enabled by someone else.)
+        if handler_ptr != 0 {
+            let handler: IrqHandler = unsafe { core::mem::transmute(handler_ptr) };
+            handler();
+        }
+        write_reg(CLAIM_COMPLETE, irq as u32);
+    }
+}
+
+fn handle_external_interrupt(_f: &mut TrapFrame) {
+    handle_irqs();
+}
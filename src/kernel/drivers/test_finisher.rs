@@ -0,0 +1,31 @@
+//! SiFive test device(`sifive,test0`/syscon) of QEMU `virt`: Exit QEMU with a status code.
+//!
+//! | written value            | effect                       |
+//! |--------------------------|------------------------------|
+//! | `0x5555`                 | exit QEMU with status 0      |
+//! | `(code << 16) \| 0x3333` | exit QEMU with status `code` |
+//! - ref: https://github.com/qemu/qemu/blob/master/hw/misc/sifive_test.c
+use crate::power;
+use crate::sbi::srst::ResetReason;
+
+pub const BASE: usize = 0x10_0000;
+pub const SIZE: usize = 0x1000;
+
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_PASS: u32 = 0x5555;
+
+/// Exit QEMU with `code`. (The host sees it as the exit status of qemu.)
+///
+/// If this is not QEMU(the write has no effect), power off by SBI instead.
+pub fn exit_qemu(code: u16) -> ! {
+    let value = match code {
+        0 => FINISHER_PASS,
+        code => (code as u32) << 16 | FINISHER_FAIL,
+    };
+    unsafe { (BASE as *mut u32).write_volatile(value) };
+
+    power::power_off(match code {
+        0 => ResetReason::NoReason,
+        _ => ResetReason::SystemFailure,
+    })
+}
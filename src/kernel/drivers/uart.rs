@@ -0,0 +1,85 @@
+//! NS16550A UART of QEMU `virt`.
+//! - ref: https://www.lammertbies.nl/comm/info/serial-uart
+use crate::console::ConsoleBackend;
+
+pub const BASE: usize = 0x1000_0000;
+pub const SIZE: usize = 0x100;
+/// PLIC interrupt source
+pub const IRQ: usize = 10;
+
+/// UART0 of QEMU `virt`
+pub static UART: Ns16550a = Ns16550a::new(BASE);
+
+/// Receive buffer(read), Transmit holding(write)
+const RBR_THR: usize = 0;
+/// Interrupt enable
+const IER: usize = 1;
+/// FIFO control(write)
+const FCR: usize = 2;
+/// Line control
+const LCR: usize = 3;
+/// Line status
+const LSR: usize = 5;
+
+/// Received data available interrupt
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+/// Enable & clear the FIFOs
+const FCR_FIFO_ENABLE: u8 = 0b111;
+/// 8bit, no parity, 1 stop bit
+const LCR_8N1: u8 = 0b11;
+/// Divisor latch access
+const LCR_DLAB: u8 = 1 << 7;
+/// Data ready
+const LSR_DR: u8 = 1 << 0;
+/// Transmit holding register empty
+const LSR_THRE: u8 = 1 << 5;
+
+pub struct Ns16550a {
+    base: usize,
+}
+
+impl Ns16550a {
+    pub const fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    fn read(&self, reg: usize) -> u8 {
+        unsafe { ((self.base + reg) as *const u8).read_volatile() }
+    }
+
+    fn write(&self, reg: usize, value: u8) {
+        unsafe { ((self.base + reg) as *mut u8).write_volatile(value) }
+    }
+
+    /// Raise IRQ while the received data is available.
+    pub fn enable_rx_interrupt(&self) {
+        self.write(IER, IER_RX_AVAILABLE);
+    }
+}
+
+impl ConsoleBackend for Ns16550a {
+    /// 8N1, FIFO enabled, interrupts disabled.
+    fn init(&self) {
+        self.write(IER, 0);
+        // Divisor 1 (QEMU ignores the baud rate.)
+        self.write(LCR, LCR_DLAB);
+        self.write(RBR_THR, 1);
+        self.write(IER, 0);
+        self.write(LCR, LCR_8N1);
+        self.write(FCR, FCR_FIFO_ENABLE);
+    }
+
+    fn put_byte(&self, byte: u8) {
+        while self.read(LSR) & LSR_THRE == 0 {
+            core::hint::spin_loop();
+        }
+        self.write(RBR_THR, byte);
+    }
+
+    fn get_byte(&self) -> Option<u8> {
+        match self.read(LSR) & LSR_DR != 0 {
+            true => Some(self.read(RBR_THR)),
+            false => None,
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! virtio-mmio transport & split virtqueue.
+//!
+//! QEMU `virt` has 8 virtio-mmio slots. Both the legacy(version 1, QEMU default) and
+//! the modern(version 2, `-global virtio-mmio.force-legacy=false`) register layouts are supported.
+//!
+//! The requests are polled (interrupts are not used), because the kernel runs with interrupts
+//! disabled.
+//! - ref: https://docs.oasis-open.org/virtio/virtio/v1.2/virtio-v1.2.html (4.2 Virtio Over MMIO)
+use crate::pages::{alloc_pages, PAGE_SIZE};
+use core::fmt;
+use core::sync::atomic::{fence, Ordering};
+
+/// The first slot
+pub const BASE: usize = 0x1000_1000;
+const SLOT_SIZE: usize = 0x1000;
+const SLOT_COUNT: usize = 8;
+/// All slots
+pub const SIZE: usize = SLOT_SIZE * SLOT_COUNT;
+
+/// "virt"
+const MAGIC: u32 = 0x7472_6976;
+
+// Register offsets
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_DEVICE_FEATURES: usize = 0x010;
+const REG_DEVICE_FEATURES_SEL: usize = 0x014;
+const REG_DRIVER_FEATURES: usize = 0x020;
+const REG_DRIVER_FEATURES_SEL: usize = 0x024;
+/// legacy only
+const REG_GUEST_PAGE_SIZE: usize = 0x028;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+/// legacy only
+const REG_QUEUE_ALIGN: usize = 0x03c;
+/// legacy only
+const REG_QUEUE_PFN: usize = 0x040;
+/// modern only
+const REG_QUEUE_READY: usize = 0x044;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+/// modern only (Low, High = +4)
+const REG_QUEUE_DESC: usize = 0x080;
+const REG_QUEUE_DRIVER: usize = 0x090;
+const REG_QUEUE_DEVICE: usize = 0x0a0;
+/// Device specific configuration
+const REG_CONFIG: usize = 0x100;
+
+// Device status bits
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_FAILED: u32 = 128;
+
+/// The device conforms to virtio 1.0+. (Must be accepted by a modern driver)
+const FEATURE_VERSION_1: u64 = 1 << 32;
+
+pub const DEVICE_ID_BLOCK: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VirtioError {
+    /// The device rejected the feature set.
+    FeaturesRejected,
+    /// The queue doesn't exist or is already used.
+    QueueUnavailable,
+}
+
+impl fmt::Display for VirtioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FeaturesRejected => write!(f, "features rejected"),
+            Self::QueueUnavailable => write!(f, "queue unavailable"),
+        }
+    }
+}
+
+/// One virtio-mmio device
+pub struct VirtioMmio {
+    base: usize,
+    /// 1: legacy, 2: modern
+    version: u32,
+}
+
+impl VirtioMmio {
+    /// Find the first device of `device_id` in the slots.
+    pub fn probe(device_id: u32) -> Option<Self> {
+        (0..SLOT_COUNT)
+            .map(|slot| BASE + slot * SLOT_SIZE)
+            .map(|base| {
+                let read = |reg: usize| unsafe { ((base + reg) as *const u32).read_volatile() };
+                (
+                    base,
+                    read(REG_MAGIC),
+                    read(REG_VERSION),
+                    read(REG_DEVICE_ID),
+                )
+            })
+            .find(|(_, magic, version, id)| {
+                *magic == MAGIC && matches!(version, 1 | 2) && *id == device_id
+            })
+            .map(|(base, _, version, _)| Self { base, version })
+    }
+
+    /// PLIC interrupt source of this device
+    pub fn irq(&self) -> usize {
+        1 + (self.base - BASE) / SLOT_SIZE
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { ((self.base + reg) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, reg: usize, value: u32) {
+        unsafe { ((self.base + reg) as *mut u32).write_volatile(value) }
+    }
+
+    fn write_u64(&self, reg: usize, value: u64) {
+        self.write(reg, value as u32);
+        self.write(reg + 4, (value >> 32) as u32);
+    }
+
+    /// Read the device specific configuration at `offset`.
+    pub fn read_config_u32(&self, offset: usize) -> u32 {
+        self.read(REG_CONFIG + offset)
+    }
+
+    /// Reset & negotiate the features. (`VERSION_1` is added for the modern device.)
+    ///
+    /// # Return
+    /// Device features. (All bits, not only the accepted ones)
+    pub fn init(&self, driver_features: u64) -> Result<u64, VirtioError> {
+        self.write(REG_STATUS, 0);
+        self.write(REG_STATUS, STATUS_ACKNOWLEDGE);
+        self.write(REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let mut device_features = 0;
+        for sel in 0..2 {
+            self.write(REG_DEVICE_FEATURES_SEL, sel);
+            device_features |= (self.read(REG_DEVICE_FEATURES) as u64) << (32 * sel);
+        }
+        let driver_features = match self.version {
+            1 => driver_features,
+            _ => driver_features | FEATURE_VERSION_1,
+        } & device_features;
+        for sel in 0..2 {
+            self.write(REG_DRIVER_FEATURES_SEL, sel);
+            self.write(REG_DRIVER_FEATURES, (driver_features >> (32 * sel)) as u32);
+        }
+
+        // The legacy device has no FEATURES_OK.
+        if self.version != 1 {
+            self.write(REG_STATUS, self.read(REG_STATUS) | STATUS_FEATURES_OK);
+            if self.read(REG_STATUS) & STATUS_FEATURES_OK == 0 {
+                self.write(REG_STATUS, STATUS_FAILED);
+                return Err(VirtioError::FeaturesRejected);
+            }
+        }
+        Ok(device_features)
+    }
+
+    /// Hand `queue` to the device as the queue `index`.
+    pub fn setup_queue(&self, index: u32, queue: &Virtqueue) -> Result<(), VirtioError> {
+        self.write(REG_QUEUE_SEL, index);
+        let ready = match self.version {
+            1 => self.read(REG_QUEUE_PFN) != 0,
+            _ => self.read(REG_QUEUE_READY) != 0,
+        };
+        if ready || (self.read(REG_QUEUE_NUM_MAX) as usize) < QUEUE_SIZE {
+            return Err(VirtioError::QueueUnavailable);
+        }
+        self.write(REG_QUEUE_NUM, QUEUE_SIZE as u32);
+        match self.version {
+            1 => {
+                self.write(REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+                self.write(REG_QUEUE_ALIGN, PAGE_SIZE as u32);
+                self.write(REG_QUEUE_PFN, (queue.base / PAGE_SIZE) as u32);
+            }
+            _ => {
+                self.write_u64(REG_QUEUE_DESC, queue.desc_addr() as u64);
+                self.write_u64(REG_QUEUE_DRIVER, queue.avail_addr() as u64);
+                self.write_u64(REG_QUEUE_DEVICE, queue.used_addr() as u64);
+                self.write(REG_QUEUE_READY, 1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the device after the queues are set up.
+    pub fn driver_ok(&self) {
+        self.write(REG_STATUS, self.read(REG_STATUS) | STATUS_DRIVER_OK);
+    }
+
+    /// Tell the device that `queue` has new buffers.
+    pub fn notify(&self, queue: u32) {
+        fence(Ordering::SeqCst);
+        self.write(REG_QUEUE_NOTIFY, queue);
+    }
+
+    /// Clear the interrupt. (Not handled, but keep the PLIC line low.)
+    pub fn ack_interrupt(&self) {
+        self.write(REG_INTERRUPT_ACK, self.read(REG_INTERRUPT_STATUS));
+    }
+}
+
+/// Descriptors per queue
+pub const QUEUE_SIZE: usize = 8;
+
+/// The next descriptor is chained.
+pub const DESC_F_NEXT: u16 = 1;
+/// The buffer is written by the device.
+pub const DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+struct Desc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct Avail {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C)]
+struct Used {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; QUEUE_SIZE],
+}
+
+/// Split virtqueue in 2 physically contiguous pages. (The layout of the legacy interface)
+///
+/// | page | content                            |
+/// |------|------------------------------------|
+/// | 0    | descriptor table, available ring   |
+/// | 1    | used ring (`QueueAlign` = 4096)    |
+pub struct Virtqueue {
+    /// Physical(= kernel virtual) address
+    base: usize,
+    last_used_idx: u16,
+}
+
+/// Only accessed under the lock of the driver.
+unsafe impl Send for Virtqueue {}
+
+impl Virtqueue {
+    pub fn new() -> Self {
+        Self {
+            base: alloc_pages(2).into(),
+            last_used_idx: 0,
+        }
+    }
+
+    fn desc_addr(&self) -> usize {
+        self.base
+    }
+
+    fn avail_addr(&self) -> usize {
+        self.base + core::mem::size_of::<Desc>() * QUEUE_SIZE
+    }
+
+    fn used_addr(&self) -> usize {
+        self.base + PAGE_SIZE
+    }
+
+    /// Chain `buffers`(physical address, length, flags) from the descriptor 0 and make it
+    /// available.
+    ///
+    /// # Panics
+    /// More than `QUEUE_SIZE` buffers
+    pub fn push_chain(&mut self, buffers: &[(usize, usize, u16)]) {
+        assert!(buffers.len() <= QUEUE_SIZE, "too many virtqueue buffers");
+        let descs = self.desc_addr() as *mut Desc;
+        for (i, (addr, len, flags)) in buffers.iter().enumerate() {
+            let has_next = i + 1 < buffers.len();
+            let desc = Desc {
+                addr: *addr as u64,
+                len: *len as u32,
+                flags: flags | if has_next { DESC_F_NEXT } else { 0 },
+                next: if has_next { i as u16 + 1 } else { 0 },
+            };
+            unsafe { descs.add(i).write_volatile(desc) };
+        }
+
+        let avail = self.avail_addr() as *mut Avail;
+        unsafe {
+            let idx = core::ptr::addr_of!((*avail).idx).read_volatile();
+            core::ptr::addr_of_mut!((*avail).ring[idx as usize % QUEUE_SIZE]).write_volatile(0);
+            // The ring entry must be visible before the index.
+            fence(Ordering::SeqCst);
+            core::ptr::addr_of_mut!((*avail).idx).write_volatile(idx.wrapping_add(1));
+        }
+    }
+
+    /// Spin until the device uses the chain.
+    pub fn wait_used(&mut self) {
+        let used = self.used_addr() as *const Used;
+        while unsafe { core::ptr::addr_of!((*used).idx).read_volatile() } == self.last_used_idx {
+            core::hint::spin_loop();
+        }
+        fence(Ordering::SeqCst);
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+    }
+}
+
+impl Default for Virtqueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,149 @@
+//! virtio block device. (`-device virtio-blk-device`)
+//! - ref: https://docs.oasis-open.org/virtio/virtio/v1.2/virtio-v1.2.html (5.2 Block Device)
+use super::virtio::{VirtioMmio, Virtqueue, DESC_F_WRITE, DEVICE_ID_BLOCK};
+use crate::block::{self, BlockDevice, BlockError, SECTOR_SIZE};
+use crate::pages::alloc_pages;
+use crate::{info, warn};
+use kernel::spinlock::SpinLock;
+
+/// The device is read only.
+const FEATURE_RO: u64 = 1 << 5;
+
+const REQ_TYPE_IN: u32 = 0;
+const REQ_TYPE_OUT: u32 = 1;
+
+const STATUS_OK: u8 = 0;
+
+/// Shared with the device (in a DMA page)
+#[repr(C)]
+struct Request {
+    // header (device readable)
+    req_type: u32,
+    reserved: u32,
+    sector: u64,
+    /// device writable on read, device readable on write
+    data: [u8; SECTOR_SIZE],
+    /// device writable
+    status: u8,
+}
+
+struct VirtioBlkInner {
+    mmio: VirtioMmio,
+    queue: Virtqueue,
+    /// Physical(= kernel virtual) address of the [`Request`]
+    request: *mut Request,
+    sector_count: u64,
+    read_only: bool,
+}
+
+/// Only accessed under the lock.
+unsafe impl Send for VirtioBlkInner {}
+
+impl VirtioBlkInner {
+    /// One request at a time: submit & poll.
+    fn submit(&mut self, req_type: u32, sector: u64) -> Result<(), BlockError> {
+        if sector >= self.sector_count {
+            return Err(BlockError::OutOfRange(sector));
+        }
+        let request = unsafe { &mut *self.request };
+        request.req_type = req_type;
+        request.reserved = 0;
+        request.sector = sector;
+        request.status = u8::MAX;
+
+        let base = self.request as usize;
+        let data_flags = match req_type {
+            REQ_TYPE_IN => DESC_F_WRITE,
+            _ => 0,
+        };
+        self.queue.push_chain(&[
+            (base, 16, 0),
+            (base + 16, SECTOR_SIZE, data_flags),
+            (base + 16 + SECTOR_SIZE, 1, DESC_F_WRITE),
+        ]);
+        self.mmio.notify(0);
+        self.queue.wait_used();
+        self.mmio.ack_interrupt();
+
+        match unsafe { core::ptr::addr_of!((*self.request).status).read_volatile() } {
+            STATUS_OK => Ok(()),
+            _ => Err(BlockError::Io),
+        }
+    }
+}
+
+pub struct VirtioBlk {
+    inner: SpinLock<Option<VirtioBlkInner>>,
+}
+
+/// The first virtio block device
+pub static VIRTIO_BLK: VirtioBlk = VirtioBlk {
+    inner: SpinLock::new(None),
+};
+
+impl BlockDevice for VirtioBlk {
+    fn sector_count(&self) -> u64 {
+        self.inner
+            .lock()
+            .as_ref()
+            .map_or(0, |inner| inner.sector_count)
+    }
+
+    fn read_sector(&self, sector: u64, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), BlockError> {
+        let mut inner = self.inner.lock();
+        let inner = inner.as_mut().ok_or(BlockError::Io)?;
+        inner.submit(REQ_TYPE_IN, sector)?;
+        buf.copy_from_slice(unsafe { &(*inner.request).data });
+        Ok(())
+    }
+
+    fn write_sector(&self, sector: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), BlockError> {
+        let mut inner = self.inner.lock();
+        let inner = inner.as_mut().ok_or(BlockError::Io)?;
+        if inner.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        unsafe { (*inner.request).data.copy_from_slice(buf) };
+        inner.submit(REQ_TYPE_OUT, sector)
+    }
+}
+
+/// Probe the virtio-mmio slots & register the first block device as the root disk.
+pub fn init() {
+    let Some(mmio) = VirtioMmio::probe(DEVICE_ID_BLOCK) else {
+        info!("virtio-blk: no disk");
+        return;
+    };
+    let features = match mmio.init(FEATURE_RO) {
+        Ok(features) => features,
+        Err(err) => {
+            warn!("virtio-blk: {}", err);
+            return;
+        }
+    };
+    let queue = Virtqueue::new();
+    if let Err(err) = mmio.setup_queue(0, &queue) {
+        warn!("virtio-blk: {}", err);
+        return;
+    }
+    mmio.driver_ok();
+
+    // config: u64 capacity(in 512 bytes sectors)
+    let sector_count = mmio.read_config_u32(0) as u64 | (mmio.read_config_u32(4) as u64) << 32;
+    let read_only = features & FEATURE_RO != 0;
+    info!(
+        "virtio-blk: v{} irq {} {} sectors{}",
+        mmio.version(),
+        mmio.irq(),
+        sector_count,
+        if read_only { " (read only)" } else { "" }
+    );
+    *VIRTIO_BLK.inner.lock() = Some(VirtioBlkInner {
+        mmio,
+        queue,
+        request: usize::from(alloc_pages(1)) as *mut Request,
+        sector_count,
+        read_only,
+    });
+    block::register_root_device(&VIRTIO_BLK);
+}
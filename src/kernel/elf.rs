@@ -0,0 +1,215 @@
+//! Minimal ELF32(RISC-V) loader for statically linked executables.
+//! - ref: https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.eheader.html
+use crate::drivers::overlaps_mmio;
+use crate::pages::{map_user_pages, MapError, PAGE_R, PAGE_SIZE, PAGE_W, PAGE_X};
+use kernel::addr::align_up;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const PT_PHDR: u32 = 6;
+/// Segment flags
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+const PF_R: u32 = 1 << 2;
+
+/// `e_ident[EI_OSABI]` values
+pub const ELFOSABI_NONE: u8 = 0;
+pub const ELFOSABI_LINUX: u8 = 3;
+pub const ELFOSABI_STANDALONE: u8 = 255;
+
+#[derive(Debug)]
+pub enum ElfError {
+    /// Not ELF32 little endian RISC-V executable
+    Unsupported,
+    /// Header or segment is out of the image
+    Truncated,
+    /// Segment overlaps the kernel or the MMIO registers(or exceeds the user address limit)
+    BadAddress(usize),
+    OutOfMemory,
+}
+
+impl From<MapError> for ElfError {
+    fn from(err: MapError) -> Self {
+        match err {
+            MapError::KernelPage(vaddr) => Self::BadAddress(vaddr),
+            MapError::OutOfMemory => Self::OutOfMemory,
+        }
+    }
+}
+
+/// ELF32 header
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct Elf32Ehdr {
+    e_ident: [u8; 16],
+    e_type: u16,
+    e_machine: u16,
+    e_version: u32,
+    e_entry: u32,
+    e_phoff: u32,
+    e_shoff: u32,
+    e_flags: u32,
+    e_ehsize: u16,
+    e_phentsize: u16,
+    e_phnum: u16,
+    e_shentsize: u16,
+    e_shnum: u16,
+    e_shstrndx: u16,
+}
+
+/// ELF32 program header
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct Elf32Phdr {
+    p_type: u32,
+    p_offset: u32,
+    p_vaddr: u32,
+    p_paddr: u32,
+    p_filesz: u32,
+    p_memsz: u32,
+    p_flags: u32,
+    p_align: u32,
+}
+
+/// Parsed ELF image (embedded in the kernel)
+pub struct Elf<'a> {
+    image: &'a [u8],
+    ehdr: Elf32Ehdr,
+}
+
+/// Result of [`Elf::load`]. Needed by the initial stack(auxv) & brk.
+#[derive(Clone, Copy, Debug)]
+pub struct LoadedElf {
+    pub entry: usize,
+    /// Virtual address of the program headers (AT_PHDR)
+    pub phdr: usize,
+    pub phent: usize,
+    pub phnum: usize,
+    /// Page aligned end of the last segment. (initial brk)
+    pub end: usize,
+}
+
+/// Is `image` an ELF file?
+pub fn is_elf(image: &[u8]) -> bool {
+    image.starts_with(&ELF_MAGIC)
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(image: &'a [u8]) -> Result<Self, ElfError> {
+        if image.len() < core::mem::size_of::<Elf32Ehdr>() {
+            return Err(ElfError::Truncated);
+        }
+        let ehdr = unsafe { (image.as_ptr() as *const Elf32Ehdr).read_unaligned() };
+        if !is_elf(image)
+            || ehdr.e_ident[4] != ELFCLASS32
+            || ehdr.e_ident[5] != ELFDATA2LSB
+            || ehdr.e_type != ET_EXEC
+            || ehdr.e_machine != EM_RISCV
+            || ehdr.e_phentsize as usize != core::mem::size_of::<Elf32Phdr>()
+        {
+            return Err(ElfError::Unsupported);
+        }
+        let phdrs_end = ehdr.e_phoff as usize + ehdr.e_phnum as usize * ehdr.e_phentsize as usize;
+        if phdrs_end > image.len() {
+            return Err(ElfError::Truncated);
+        }
+
+        Ok(Self { image, ehdr })
+    }
+
+    /// `e_ident[EI_OSABI]`
+    pub fn os_abi(&self) -> u8 {
+        self.ehdr.e_ident[7]
+    }
+
+    fn phdrs(&self) -> impl Iterator<Item = Elf32Phdr> + '_ {
+        (0..self.ehdr.e_phnum as usize).map(|i| {
+            let offset = self.ehdr.e_phoff as usize + i * core::mem::size_of::<Elf32Phdr>();
+            unsafe { (self.image.as_ptr().add(offset) as *const Elf32Phdr).read_unaligned() }
+        })
+    }
+
+    /// Copy all `PT_LOAD` segments to newly allocated user pages.
+    ///
+    /// # Parameters
+    /// - root_ppn: proc root node(satp)
+    /// - vaddr_limit: segments must end below this address
+    pub fn load(&self, root_ppn: usize, vaddr_limit: usize) -> Result<LoadedElf, ElfError> {
+        let mut end = 0;
+        let mut phdr = None;
+        for ph in self.phdrs() {
+            match ph.p_type {
+                PT_LOAD => {}
+                PT_PHDR => {
+                    phdr = Some(ph.p_vaddr as usize);
+                    continue;
+                }
+                _ => continue,
+            }
+
+            let (vaddr, memsz, offset, filesz) = (
+                ph.p_vaddr as usize,
+                ph.p_memsz as usize,
+                ph.p_offset as usize,
+                ph.p_filesz as usize,
+            );
+            let seg_end = vaddr
+                .checked_add(memsz)
+                .filter(|seg_end| *seg_end <= vaddr_limit && !overlaps_mmio(vaddr, *seg_end))
+                .ok_or(ElfError::BadAddress(vaddr))?;
+            // The file bytes must fit in the segment.
+            if filesz > memsz {
+                return Err(ElfError::Unsupported);
+            }
+            let file = (offset.checked_add(filesz))
+                .and_then(|file_end| self.image.get(offset..file_end))
+                .ok_or(ElfError::Truncated)?;
+            // The program headers are usually in the first segment.
+            let phoff = self.ehdr.e_phoff as usize;
+            if phdr.is_none() && offset <= phoff && phoff - offset < filesz {
+                phdr = Some(vaddr + (phoff - offset));
+            }
+
+            let mut flags = 0;
+            if ph.p_flags & PF_R != 0 {
+                flags |= PAGE_R;
+            }
+            if ph.p_flags & PF_W != 0 {
+                flags |= PAGE_W;
+            }
+            if ph.p_flags & PF_X != 0 {
+                flags |= PAGE_X;
+            }
+            map_user_pages(root_ppn, vaddr, seg_end, flags, |page_vaddr, page_paddr| {
+                // Copy the file bytes overlapping with this page. (The rest is already 0 filled.)
+                let copy_start = page_vaddr.max(vaddr);
+                let copy_end = (page_vaddr + PAGE_SIZE).min(vaddr + filesz);
+                if copy_start < copy_end {
+                    let src = &file[copy_start - vaddr..copy_end - vaddr];
+                    unsafe {
+                        core::slice::from_raw_parts_mut(
+                            (page_paddr + (copy_start - page_vaddr)) as *mut u8,
+                            src.len(),
+                        )
+                        .copy_from_slice(src);
+                    }
+                }
+            })?;
+            end = end.max(seg_end);
+        }
+
+        Ok(LoadedElf {
+            entry: self.ehdr.e_entry as usize,
+            phdr: phdr.unwrap_or(0),
+            phent: self.ehdr.e_phentsize as usize,
+            phnum: self.ehdr.e_phnum as usize,
+            end: align_up(end, PAGE_SIZE),
+        })
+    }
+}
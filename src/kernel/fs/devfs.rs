@@ -0,0 +1,102 @@
+//! Device files, mounted at `/dev`.
+//!
+//! | name      | device                                  |
+//! |-----------|-----------------------------------------|
+//! | `console` | the console TTY (see [`crate::tty`])    |
+use super::vfs::{DirEntry, Inode};
+use crate::tty;
+use abi::fs::{FileKind, Stat};
+use abi::Errno;
+use alloc::{string::String, sync::Arc};
+
+/// `/dev`
+struct DevDir;
+
+impl Inode for DevDir {
+    fn stat(&self) -> Stat {
+        Stat {
+            kind: FileKind::Directory,
+            size: 0,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        match name {
+            "console" => Ok(console()),
+            _ => Err(Errno::ENOENT),
+        }
+    }
+
+    /// Devices are fixed.
+    fn create(&self, _name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn mkdir(&self, _name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn rmdir(&self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn rename(&self, _old_name: &str, _new_dir: &dyn Inode, _new_name: &str) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+        Ok((index == 0).then(|| DirEntry {
+            name: String::from("console"),
+            kind: FileKind::CharDevice,
+        }))
+    }
+}
+
+/// The console TTY. The offset is ignored.
+struct Console;
+
+impl Inode for Console {
+    fn stat(&self) -> Stat {
+        Stat {
+            kind: FileKind::CharDevice,
+            size: 0,
+        }
+    }
+
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        Ok(tty::read(buf))
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        tty::write(buf);
+        Ok(buf.len())
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize, Errno> {
+        tty::ioctl(request, arg)
+    }
+
+    /// Read once. (up to one line in the canonical mode)
+    fn read_user(&self, _offset: usize, buf: usize, len: usize) -> Result<usize, Errno> {
+        tty::read_to_user(buf, len)
+    }
+
+    /// Not interleaved with other writers.
+    fn write_user(&self, _offset: usize, buf: usize, len: usize) -> Result<usize, Errno> {
+        tty::write_from_user(buf, len)
+    }
+}
+
+/// The root of `/dev`
+pub fn root() -> Arc<dyn Inode> {
+    Arc::new(DevDir)
+}
+
+/// `/dev/console`
+pub fn console() -> Arc<dyn Inode> {
+    Arc::new(Console)
+}
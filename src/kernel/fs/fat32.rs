@@ -0,0 +1,1373 @@
+//! FAT32 file system on a block device.
+//!
+//! Images made by the host tools (`mkfs.fat -F 32`, `mcopy`) can be mounted as they are.
+//! Nothing is cached: every access reads & writes the sectors of the device.
+//!
+//! | region         | sectors                                   |
+//! |----------------|-------------------------------------------|
+//! | reserved       | the boot sector(BPB), FSInfo, ...         |
+//! | FAT × `fats`   | the next cluster of each cluster (32 bit) |
+//! | data           | clusters `2..cluster_count + 2`           |
+//!
+//! A directory is a cluster chain of 32 bytes entries. A long file name(LFN) is stored in the
+//! entries before the 8.3 name entry, 13 UTF-16 units each, in the reverse order.
+//!
+//! The open files are shared by the entry position, so a file renamed or unlinked while open
+//! keeps working. The clusters of an unlinked file are freed when its last open file is closed.
+//! - ref: Microsoft Extensible Firmware Initiative FAT32 File System Specification
+use super::vfs::{DirEntry, Inode};
+use crate::block::{BlockDevice, BlockError, SECTOR_SIZE};
+use crate::uaccess::{copy_from_user, copy_to_user};
+use crate::{info, warn};
+use abi::fs::{FileKind, Stat};
+use abi::Errno;
+use alloc::{
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use kernel::addr::{align_up, is_aligned};
+use kernel::spinlock::{SpinLock, SpinLockGuard};
+
+const ENTRY_SIZE: usize = 32;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+const ATTR_LONG_NAME_MASK: u8 = ATTR_LONG_NAME | ATTR_DIRECTORY | ATTR_ARCHIVE;
+
+/// The 1st byte of a free entry
+const ENTRY_FREE: u8 = 0xE5;
+/// The 1st byte of the free entry after the last one
+const ENTRY_END: u8 = 0x00;
+/// The 1st byte `0xE5` of a name is stored as this.
+const ENTRY_KANJI_E5: u8 = 0x05;
+/// `LDIR_Ord` of the last(physically first) LFN entry
+const LFN_LAST: u8 = 0x40;
+/// UTF-16 units per LFN entry
+const LFN_UNITS: usize = 13;
+/// Offsets of the UTF-16 units in a LFN entry
+const LFN_UNIT_OFFSETS: [usize; LFN_UNITS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// Max UTF-16 units of a long name
+const LFN_MAX: usize = 255;
+
+/// `NTRes` flags: the base / extension of the 8.3 name is lower case.
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+/// Valid bits of a FAT entry. (The upper 4 bits are reserved.)
+const FAT_MASK: u32 = 0x0FFF_FFFF;
+/// FAT entry of the last cluster of a chain (`0x0FFFFFF8..`)
+const FAT_EOC: u32 = 0x0FFF_FFFF;
+const FAT_FREE: u32 = 0;
+
+/// 1980-01-01 (The timestamps are not maintained.)
+const DATE_EPOCH: u16 = (1 << 5) | 1;
+
+const FSINFO_LEAD_SIG: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIG: u32 = 0x6141_7272;
+
+/// BIOS parameter block: the layout of the volume
+struct Bpb {
+    sectors_per_cluster: u32,
+    /// The 1st sector of the FATs
+    fat_start: u64,
+    fat_sectors: u64,
+    fats: u32,
+    /// `Some(n)`: only the FAT `n` is used. `None`: every FAT is a mirror.
+    active_fat: Option<u32>,
+    root_cluster: u32,
+    /// 0: none
+    fsinfo_sector: u64,
+    /// The 1st sector of the cluster 2
+    data_start: u64,
+    cluster_count: u32,
+}
+
+impl Bpb {
+    /// Parse the boot sector.
+    ///
+    /// # Errors
+    /// EINVAL: not a FAT32 volume, or not supported
+    fn parse(sector: &[u8; SECTOR_SIZE], sector_count: u64) -> Result<Self, Errno> {
+        let bytes_per_sector = u16_at(sector, 11) as usize;
+        let sectors_per_cluster = sector[13] as u32;
+        let reserved_sectors = u16_at(sector, 14) as u64;
+        let fats = sector[16] as u32;
+        let root_entries = u16_at(sector, 17);
+        let total_sectors = match u16_at(sector, 19) {
+            0 => u32_at(sector, 32) as u64,
+            total => total as u64,
+        };
+        let fat_sectors16 = u16_at(sector, 22);
+        let fat_sectors = u32_at(sector, 36) as u64;
+        let ext_flags = u16_at(sector, 40);
+
+        // FAT12/16 have the fixed root directory & 16 bit FAT size.
+        if sector[510..512] != [0x55, 0xAA]
+            || bytes_per_sector != SECTOR_SIZE
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fats == 0
+            || root_entries != 0
+            || fat_sectors16 != 0
+            || fat_sectors == 0
+        {
+            return Err(Errno::EINVAL);
+        }
+        let data_start = reserved_sectors + fats as u64 * fat_sectors;
+        if total_sectors > sector_count || total_sectors <= data_start {
+            return Err(Errno::EINVAL);
+        }
+        // Also limited by the entries the FAT can hold.
+        let cluster_count = ((total_sectors - data_start) / sectors_per_cluster as u64)
+            .min(fat_sectors * (SECTOR_SIZE / 4) as u64 - 2)
+            .min((FAT_MASK - 0xF) as u64) as u32;
+        let root_cluster = u32_at(sector, 44);
+        if !(2..cluster_count + 2).contains(&root_cluster) {
+            return Err(Errno::EINVAL);
+        }
+        let active_fat = (ext_flags & 0x80 != 0).then_some((ext_flags & 0xF) as u32);
+        if active_fat.is_some_and(|active| active >= fats) {
+            return Err(Errno::EINVAL);
+        }
+
+        Ok(Self {
+            sectors_per_cluster,
+            fat_start: reserved_sectors,
+            fat_sectors,
+            fats,
+            active_fat,
+            root_cluster,
+            fsinfo_sector: match u16_at(sector, 48) {
+                0xFFFF => 0,
+                fsinfo => fsinfo as u64,
+            },
+            data_start,
+            cluster_count,
+        })
+    }
+
+    fn cluster_bytes(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    /// The 1st sector of `cluster`
+    fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - 2) as u64 * self.sectors_per_cluster as u64
+    }
+
+    fn is_cluster(&self, cluster: u32) -> bool {
+        (2..self.cluster_count + 2).contains(&cluster)
+    }
+
+    /// (sector, offset) of the FAT entry of `cluster` in the FAT `fat`
+    fn fat_entry(&self, fat: u32, cluster: u32) -> (u64, usize) {
+        let offset = cluster as usize * 4;
+        let sector = self.fat_start + fat as u64 * self.fat_sectors;
+        (sector + (offset / SECTOR_SIZE) as u64, offset % SECTOR_SIZE)
+    }
+}
+
+/// Where the 8.3 name entry of a file is.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct EntryPos {
+    /// The 1st cluster of the parent directory
+    dir: u32,
+    /// Entry index in the directory
+    index: u32,
+    /// LFN entries before the 8.3 name entry
+    lfn_count: u32,
+}
+
+/// One file of a directory
+struct DirItem {
+    /// The long name, or the 8.3 name
+    name: String,
+    short: [u8; 11],
+    attr: u8,
+    first_cluster: u32,
+    size: u32,
+    pos: EntryPos,
+}
+
+impl DirItem {
+    fn kind(&self) -> FileKind {
+        match self.attr & ATTR_DIRECTORY {
+            0 => FileKind::Regular,
+            _ => FileKind::Directory,
+        }
+    }
+
+    /// FAT names are case insensitive. The 8.3 alias also matches.
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || short_to_string(&self.short, 0).eq_ignore_ascii_case(name)
+    }
+}
+
+struct State {
+    /// Where to start searching a free cluster
+    next_free: u32,
+    /// The free cluster count of FSInfo is invalidated.
+    fsinfo_invalidated: bool,
+    /// The open files, to share them by the entry position
+    nodes: Vec<Weak<FatNode>>,
+}
+
+struct Fat32 {
+    device: &'static dyn BlockDevice,
+    bpb: Bpb,
+    /// Every access to the volume is under this lock.
+    state: SpinLock<State>,
+    /// 1st clusters of the unlinked files closed. (Freed by the next access, as a node can be
+    /// dropped under the `state` lock.)
+    pending_free: SpinLock<Vec<u32>>,
+}
+
+/// Is the volume of `device` FAT32?
+pub fn detect(device: &dyn BlockDevice) -> bool {
+    let mut sector = [0u8; SECTOR_SIZE];
+    device.read_sector(0, &mut sector).is_ok() && Bpb::parse(&sector, device.sector_count()).is_ok()
+}
+
+/// Mount the FAT32 volume of `device`. Returns the root directory.
+///
+/// # Errors
+/// - EINVAL: not a FAT32 volume
+/// - EIO: the device failed
+pub fn mount(device: &'static dyn BlockDevice) -> Result<Arc<dyn Inode>, Errno> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    device.read_sector(0, &mut sector).map_err(io_error)?;
+    let bpb = Bpb::parse(&sector, device.sector_count())?;
+    info!(
+        "fat32: {} clusters of {} bytes",
+        bpb.cluster_count,
+        bpb.cluster_bytes()
+    );
+    let root_cluster = bpb.root_cluster;
+    let fs = Arc::new(Fat32 {
+        device,
+        bpb,
+        state: SpinLock::new(State {
+            next_free: 2,
+            fsinfo_invalidated: false,
+            nodes: Vec::new(),
+        }),
+        pending_free: SpinLock::new(Vec::new()),
+    });
+    Ok(Arc::new(FatNode::new(
+        fs,
+        FileKind::Directory,
+        NodeInfo {
+            first_cluster: root_cluster,
+            size: 0,
+            entry: None,
+            unlinked: false,
+        },
+    )))
+}
+
+impl Fat32 {
+    /// Lock the volume, & free the clusters of the closed unlinked files.
+    fn lock(&self) -> SpinLockGuard<'_, State> {
+        let mut state = self.state.lock();
+        let pending = core::mem::take(&mut *self.pending_free.lock());
+        for first in pending {
+            if let Err(err) = self.free_chain(&mut state, first) {
+                warn!("fat32: failed to free cluster {}: {}", first, err);
+            }
+        }
+        state
+    }
+
+    fn read_sector(&self, sector: u64) -> Result<[u8; SECTOR_SIZE], Errno> {
+        let mut buf = [0u8; SECTOR_SIZE];
+        self.device
+            .read_sector(sector, &mut buf)
+            .map_err(io_error)?;
+        Ok(buf)
+    }
+
+    fn write_sector(&self, sector: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), Errno> {
+        self.device.write_sector(sector, buf).map_err(io_error)
+    }
+
+    fn fat_get(&self, cluster: u32) -> Result<u32, Errno> {
+        let (sector, offset) = self
+            .bpb
+            .fat_entry(self.bpb.active_fat.unwrap_or(0), cluster);
+        Ok(u32_at(&self.read_sector(sector)?, offset) & FAT_MASK)
+    }
+
+    /// Set the FAT entry of `cluster` in every FAT in use.
+    fn fat_set(&self, state: &mut State, cluster: u32, value: u32) -> Result<(), Errno> {
+        self.invalidate_fsinfo(state);
+        let fats = match self.bpb.active_fat {
+            Some(active) => active..active + 1,
+            None => 0..self.bpb.fats,
+        };
+        for fat in fats {
+            let (sector, offset) = self.bpb.fat_entry(fat, cluster);
+            let mut buf = self.read_sector(sector)?;
+            let reserved = u32_at(&buf, offset) & !FAT_MASK;
+            put_u32(&mut buf, offset, reserved | value);
+            self.write_sector(sector, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// Check `cluster` read from the volume(e.g. the 1st cluster of an entry) before using it.
+    ///
+    /// # Errors
+    /// EIO: out of the data area
+    fn check_cluster(&self, cluster: u32) -> Result<(), Errno> {
+        if self.bpb.is_cluster(cluster) {
+            return Ok(());
+        }
+        warn!("fat32: invalid cluster {:#x}", cluster);
+        Err(Errno::EIO)
+    }
+
+    /// Check the 1st cluster of `item`. (0 is valid for an empty file)
+    fn check_item(&self, item: &DirItem) -> Result<(), Errno> {
+        match (item.kind(), item.first_cluster) {
+            (FileKind::Regular, 0) => Ok(()),
+            (_, first) => self.check_cluster(first),
+        }
+    }
+
+    /// The cluster after `cluster` in the chain. `None` at the end.
+    ///
+    /// # Errors
+    /// EIO: broken chain
+    fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, Errno> {
+        self.check_cluster(cluster)?;
+        match self.fat_get(cluster)? {
+            next if self.bpb.is_cluster(next) => Ok(Some(next)),
+            0x0FFF_FFF8.. => Ok(None),
+            next => {
+                warn!("fat32: broken chain: cluster {} -> {:#x}", cluster, next);
+                Err(Errno::EIO)
+            }
+        }
+    }
+
+    /// The `n`th(0 origin) cluster of the chain `first`
+    fn nth_cluster(&self, first: u32, n: usize) -> Result<u32, Errno> {
+        self.check_cluster(first)?;
+        let mut cluster = first;
+        for _ in 0..n {
+            cluster = self.next_cluster(cluster)?.ok_or(Errno::EIO)?;
+        }
+        Ok(cluster)
+    }
+
+    /// Allocate a zero filled cluster, & append it to the chain ending with `last`(0: a new
+    /// chain).
+    ///
+    /// # Errors
+    /// ENOSPC: no free cluster
+    fn alloc_cluster(&self, state: &mut State, last: u32) -> Result<u32, Errno> {
+        let count = self.bpb.cluster_count;
+        let start = match self.bpb.is_cluster(state.next_free) {
+            true => state.next_free - 2,
+            false => 0,
+        };
+        let mut cached: Option<(u64, [u8; SECTOR_SIZE])> = None;
+        let mut found = None;
+        for i in 0..count {
+            let cluster = 2 + (start + i) % count;
+            let (sector, offset) = self
+                .bpb
+                .fat_entry(self.bpb.active_fat.unwrap_or(0), cluster);
+            let buf = match cached {
+                Some((cached_sector, buf)) if cached_sector == sector => buf,
+                _ => {
+                    let buf = self.read_sector(sector)?;
+                    cached = Some((sector, buf));
+                    buf
+                }
+            };
+            if u32_at(&buf, offset) & FAT_MASK == FAT_FREE {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = found.ok_or(Errno::ENOSPC)?;
+
+        let zero = [0u8; SECTOR_SIZE];
+        let first_sector = self.bpb.cluster_sector(cluster);
+        for sector in first_sector..first_sector + self.bpb.sectors_per_cluster as u64 {
+            self.write_sector(sector, &zero)?;
+        }
+        self.fat_set(state, cluster, FAT_EOC)?;
+        if last != 0 {
+            self.fat_set(state, last, cluster)?;
+        }
+        state.next_free = cluster + 1;
+        Ok(cluster)
+    }
+
+    /// Free the chain from `first`.
+    fn free_chain(&self, state: &mut State, first: u32) -> Result<(), Errno> {
+        self.check_cluster(first)?;
+        let mut cluster = first;
+        // Bounded, in case of a loop in the FAT
+        for _ in 0..self.bpb.cluster_count {
+            let next = self.next_cluster(cluster);
+            self.fat_set(state, cluster, FAT_FREE)?;
+            match next? {
+                Some(next) => cluster = next,
+                None => break,
+            }
+        }
+        state.next_free = state.next_free.min(first);
+        Ok(())
+    }
+
+    /// Make the chain from `first`(0: empty) `clusters` long or longer. `first` is updated as
+    /// soon as a new chain is allocated, so the clusters allocated before an error are kept in it.
+    fn extend_chain(
+        &self,
+        state: &mut State,
+        first: &mut u32,
+        clusters: usize,
+    ) -> Result<(), Errno> {
+        if clusters == 0 {
+            return Ok(());
+        }
+        if *first == 0 {
+            *first = self.alloc_cluster(state, 0)?;
+        }
+        let mut cluster = *first;
+        for _ in 1..clusters {
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => self.alloc_cluster(state, cluster)?,
+            };
+        }
+        Ok(())
+    }
+
+    /// Cut the chain from `first` to `clusters`. Returns the new first. (0 if empty)
+    fn shrink_chain(&self, state: &mut State, first: u32, clusters: usize) -> Result<u32, Errno> {
+        if first == 0 {
+            return Ok(0);
+        }
+        if clusters == 0 {
+            self.free_chain(state, first)?;
+            return Ok(0);
+        }
+        let last = self.nth_cluster(first, clusters - 1)?;
+        if let Some(next) = self.next_cluster(last)? {
+            self.fat_set(state, last, FAT_EOC)?;
+            self.free_chain(state, next)?;
+        }
+        Ok(first)
+    }
+
+    /// FSInfo keeps the free cluster count, which this driver doesn't maintain. Mark it unknown
+    /// before the 1st change of the FATs, so that the host recounts.
+    fn invalidate_fsinfo(&self, state: &mut State) {
+        if state.fsinfo_invalidated {
+            return;
+        }
+        state.fsinfo_invalidated = true;
+        let sector = self.bpb.fsinfo_sector;
+        if sector == 0 {
+            return;
+        }
+        let Ok(mut buf) = self.read_sector(sector) else {
+            return;
+        };
+        if u32_at(&buf, 0) != FSINFO_LEAD_SIG || u32_at(&buf, 484) != FSINFO_STRUCT_SIG {
+            return;
+        }
+        put_u32(&mut buf, 488, u32::MAX);
+        put_u32(&mut buf, 492, u32::MAX);
+        if let Err(err) = self.write_sector(sector, &buf) {
+            warn!("fat32: failed to update FSInfo: {}", err);
+        }
+    }
+
+    /// Call `f(sector, range in the sector, done)` for the sectors of the bytes
+    /// `offset..offset + len` of the chain `first`, in order.
+    fn for_each_sector(
+        &self,
+        first: u32,
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(u64, core::ops::Range<usize>, usize) -> Result<(), Errno>,
+    ) -> Result<(), Errno> {
+        if len == 0 {
+            return Ok(());
+        }
+        let cluster_bytes = self.bpb.cluster_bytes();
+        let mut cluster = self.nth_cluster(first, offset / cluster_bytes)?;
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            if done != 0 && is_aligned(pos, cluster_bytes) {
+                cluster = self.next_cluster(cluster)?.ok_or(Errno::EIO)?;
+            }
+            let sector =
+                self.bpb.cluster_sector(cluster) + ((pos % cluster_bytes) / SECTOR_SIZE) as u64;
+            let start = pos % SECTOR_SIZE;
+            let n = (SECTOR_SIZE - start).min(len - done);
+            f(sector, start..start + n, done)?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Pass the bytes `offset..offset + len` of the chain `first` to `f(done, bytes)`.
+    fn read_data(
+        &self,
+        first: u32,
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(usize, &[u8]) -> Result<(), Errno>,
+    ) -> Result<(), Errno> {
+        self.for_each_sector(first, offset, len, |sector, range, done| {
+            f(done, &self.read_sector(sector)?[range])
+        })
+    }
+
+    /// Overwrite the bytes `offset..offset + len` of the chain `first` with `fill(done, bytes)`.
+    /// (The chain is long enough.)
+    fn write_data(
+        &self,
+        first: u32,
+        offset: usize,
+        len: usize,
+        mut fill: impl FnMut(usize, &mut [u8]) -> Result<(), Errno>,
+    ) -> Result<(), Errno> {
+        self.for_each_sector(first, offset, len, |sector, range, done| {
+            let mut buf = match range.len() {
+                SECTOR_SIZE => [0u8; SECTOR_SIZE],
+                _ => self.read_sector(sector)?,
+            };
+            fill(done, &mut buf[range])?;
+            self.write_sector(sector, &buf)
+        })
+    }
+
+    /// (sector, offset) of the entry `index` of the directory `dir`
+    fn entry_location(&self, dir: u32, index: u32) -> Result<(u64, usize), Errno> {
+        let offset = index as usize * ENTRY_SIZE;
+        let cluster_bytes = self.bpb.cluster_bytes();
+        let cluster = self.nth_cluster(dir, offset / cluster_bytes)?;
+        let offset = offset % cluster_bytes;
+        Ok((
+            self.bpb.cluster_sector(cluster) + (offset / SECTOR_SIZE) as u64,
+            offset % SECTOR_SIZE,
+        ))
+    }
+
+    /// Change the entry `index` of the directory `dir` by `f`.
+    fn modify_entry(&self, dir: u32, index: u32, f: impl FnOnce(&mut [u8])) -> Result<(), Errno> {
+        let (sector, offset) = self.entry_location(dir, index)?;
+        let mut buf = self.read_sector(sector)?;
+        f(&mut buf[offset..offset + ENTRY_SIZE]);
+        self.write_sector(sector, &buf)
+    }
+
+    /// The files of the directory `dir`. (without `.`, `..` & the volume label)
+    fn read_dir(&self, dir: u32) -> Result<Vec<DirItem>, Errno> {
+        let mut items = Vec::new();
+        let mut lfn = LfnBuilder::default();
+        let mut index = 0;
+        self.check_cluster(dir)?;
+        let mut cluster = Some(dir);
+        while let Some(current) = cluster {
+            let first_sector = self.bpb.cluster_sector(current);
+            for sector in first_sector..first_sector + self.bpb.sectors_per_cluster as u64 {
+                let buf = self.read_sector(sector)?;
+                for offset in (0..SECTOR_SIZE).step_by(ENTRY_SIZE) {
+                    let entry = &buf[offset..offset + ENTRY_SIZE];
+                    match entry[0] {
+                        ENTRY_END => return Ok(items),
+                        ENTRY_FREE => lfn.clear(),
+                        _ if entry[11] & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME => lfn.push(entry),
+                        _ if entry[11] & ATTR_VOLUME_ID != 0 || entry[0] == b'.' => lfn.clear(),
+                        _ => {
+                            let short: [u8; 11] = entry[..11].try_into().unwrap();
+                            let (name, lfn_count) = lfn.take(&short);
+                            items.push(DirItem {
+                                name: name.unwrap_or_else(|| short_to_string(&short, entry[12])),
+                                short,
+                                attr: entry[11],
+                                first_cluster: (u16_at(entry, 20) as u32) << 16
+                                    | u16_at(entry, 26) as u32,
+                                size: u32_at(entry, 28),
+                                pos: EntryPos {
+                                    dir,
+                                    index,
+                                    lfn_count,
+                                },
+                            });
+                        }
+                    }
+                    index += 1;
+                }
+            }
+            cluster = self.next_cluster(current)?;
+        }
+        Ok(items)
+    }
+
+    /// Index of `count` free entries in a row in the directory `dir`. The directory is extended
+    /// if there are not.
+    fn find_free_entries(&self, state: &mut State, dir: u32, count: u32) -> Result<u32, Errno> {
+        let (mut run_start, mut run_len) = (0, 0);
+        let mut index = 0;
+        self.check_cluster(dir)?;
+        let mut last = dir;
+        let mut cluster = Some(dir);
+        while let Some(current) = cluster {
+            let first_sector = self.bpb.cluster_sector(current);
+            for sector in first_sector..first_sector + self.bpb.sectors_per_cluster as u64 {
+                let buf = self.read_sector(sector)?;
+                for offset in (0..SECTOR_SIZE).step_by(ENTRY_SIZE) {
+                    if matches!(buf[offset], ENTRY_END | ENTRY_FREE) {
+                        if run_len == 0 {
+                            run_start = index;
+                        }
+                        run_len += 1;
+                        if run_len == count {
+                            return Ok(run_start);
+                        }
+                    } else {
+                        run_len = 0;
+                    }
+                    index += 1;
+                }
+            }
+            last = current;
+            cluster = self.next_cluster(current)?;
+        }
+        if run_len == 0 {
+            run_start = index;
+        }
+        let per_cluster = (self.bpb.cluster_bytes() / ENTRY_SIZE) as u32;
+        while run_len < count {
+            last = self.alloc_cluster(state, last)?;
+            run_len += per_cluster;
+        }
+        Ok(run_start)
+    }
+
+    /// Add the entries of `name` to the directory `dir`. The 8.3 name entry is `entry` with the
+    /// name. `ignore` is not counted as the same name. (The source of a rename)
+    ///
+    /// # Errors
+    /// - EEXIST: already exists
+    /// - EINVAL: invalid character in `name`
+    /// - ENAMETOOLONG
+    /// - ENOSPC: the directory can't be extended
+    fn add_entry(
+        &self,
+        state: &mut State,
+        dir: u32,
+        name: &str,
+        ignore: Option<EntryPos>,
+        mut entry: [u8; ENTRY_SIZE],
+    ) -> Result<EntryPos, Errno> {
+        check_name(name)?;
+        let items = self.read_dir(dir)?;
+        if (items.iter()).any(|item| item.matches(name) && Some(item.pos) != ignore) {
+            return Err(Errno::EEXIST);
+        }
+        let (short, needs_lfn) = short_name(name, &items);
+
+        let mut entries = match needs_lfn {
+            true => lfn_entries(name, checksum(&short)),
+            false => Vec::new(),
+        };
+        entry[..11].copy_from_slice(&short);
+        entries.push(entry);
+
+        let start = self.find_free_entries(state, dir, entries.len() as u32)?;
+        for (i, entry) in entries.iter().enumerate() {
+            self.modify_entry(dir, start + i as u32, |dst| dst.copy_from_slice(entry))?;
+        }
+        Ok(EntryPos {
+            dir,
+            index: start + entries.len() as u32 - 1,
+            lfn_count: entries.len() as u32 - 1,
+        })
+    }
+
+    /// Mark the entries of `pos` free.
+    fn remove_entry(&self, pos: EntryPos) -> Result<(), Errno> {
+        for index in pos.index - pos.lfn_count..=pos.index {
+            self.modify_entry(pos.dir, index, |entry| entry[0] = ENTRY_FREE)?;
+        }
+        Ok(())
+    }
+
+    /// Free the clusters of the removed `item`, or leave it to the open file.
+    fn release(&self, state: &mut State, item: &DirItem) -> Result<(), Errno> {
+        if let Some(node) = self.open_node(state, item.pos) {
+            let mut info = node.info.lock();
+            info.entry = None;
+            info.unlinked = true;
+            return Ok(());
+        }
+        match item.first_cluster {
+            0 => Ok(()),
+            first => self.free_chain(state, first),
+        }
+    }
+
+    /// The open file of `pos`
+    fn open_node(&self, state: &mut State, pos: EntryPos) -> Option<Arc<FatNode>> {
+        state.nodes.retain(|node| node.strong_count() > 0);
+        (state.nodes.iter())
+            .filter_map(|node| node.upgrade())
+            .find(|node| node.info.lock().entry == Some(pos))
+    }
+
+    /// `..` of a directory: 0 for the root
+    fn parent_cluster(&self, dir: u32) -> u32 {
+        match dir == self.bpb.root_cluster {
+            true => 0,
+            false => dir,
+        }
+    }
+}
+
+struct NodeInfo {
+    /// 0: empty file
+    first_cluster: u32,
+    size: u32,
+    /// `None` for the root & unlinked files
+    entry: Option<EntryPos>,
+    /// The clusters are freed on drop.
+    unlinked: bool,
+}
+
+/// A file or directory of a FAT32 volume
+struct FatNode {
+    fs: Arc<Fat32>,
+    kind: FileKind,
+    /// Locked after the `state` of the volume
+    info: SpinLock<NodeInfo>,
+}
+
+impl FatNode {
+    fn new(fs: Arc<Fat32>, kind: FileKind, info: NodeInfo) -> Self {
+        Self {
+            fs,
+            kind,
+            info: SpinLock::new(info),
+        }
+    }
+
+    /// The 1st cluster of this directory
+    fn dir_cluster(&self) -> Result<u32, Errno> {
+        match self.kind {
+            FileKind::Directory => Ok(self.info.lock().first_cluster),
+            _ => Err(Errno::ENOTDIR),
+        }
+    }
+
+    /// The node of `item`, shared with the open one.
+    fn node(&self, state: &mut State, item: &DirItem) -> Arc<dyn Inode> {
+        if let Some(node) = self.fs.open_node(state, item.pos) {
+            return node;
+        }
+        let node = Arc::new(FatNode::new(
+            self.fs.clone(),
+            item.kind(),
+            NodeInfo {
+                first_cluster: item.first_cluster,
+                size: item.size,
+                entry: Some(item.pos),
+                unlinked: false,
+            },
+        ));
+        state.nodes.push(Arc::downgrade(&node));
+        node
+    }
+
+    /// Write the first cluster & size to the entry.
+    fn update_entry(&self, info: &NodeInfo) -> Result<(), Errno> {
+        match info.entry {
+            Some(pos) => self.fs.modify_entry(pos.dir, pos.index, |entry| {
+                set_entry_data(entry, info.first_cluster, info.size)
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Read up to `len` bytes at `offset` by `f(done, bytes)`.
+    fn read(
+        &self,
+        offset: usize,
+        len: usize,
+        f: impl FnMut(usize, &[u8]) -> Result<(), Errno>,
+    ) -> Result<usize, Errno> {
+        if self.kind == FileKind::Directory {
+            return Err(Errno::EISDIR);
+        }
+        let _state = self.fs.lock();
+        let info = self.info.lock();
+        let len = len.min((info.size as usize).saturating_sub(offset));
+        self.fs.read_data(info.first_cluster, offset, len, f)?;
+        Ok(len)
+    }
+
+    /// Write `len` bytes filled by `fill(done, bytes)` at `offset`, allocating the clusters.
+    ///
+    /// # Errors
+    /// - EFBIG: beyond 4GiB - 1 (`DIR_FileSize` is 32 bit)
+    /// - ENOSPC: no free cluster
+    fn write(
+        &self,
+        offset: usize,
+        len: usize,
+        fill: impl FnMut(usize, &mut [u8]) -> Result<(), Errno>,
+    ) -> Result<usize, Errno> {
+        if self.kind == FileKind::Directory {
+            return Err(Errno::EISDIR);
+        }
+        if len == 0 {
+            return Ok(0);
+        }
+        let end = (offset.checked_add(len))
+            .filter(|end| u32::try_from(*end).is_ok())
+            .ok_or(Errno::EFBIG)?;
+        let fs = &self.fs;
+        let mut state = fs.lock();
+        let mut info = self.info.lock();
+        let size = info.size as usize;
+        let clusters = align_up(end, fs.bpb.cluster_bytes()) / fs.bpb.cluster_bytes();
+        let result = fs
+            .extend_chain(&mut state, &mut info.first_cluster, clusters)
+            .and_then(|()| {
+                let first = info.first_cluster;
+                // The rest of the last cluster may be garbage.
+                if offset > size {
+                    fs.write_data(first, size, offset - size, |_, bytes| {
+                        bytes.fill(0);
+                        Ok(())
+                    })?;
+                }
+                fs.write_data(first, offset, len, fill)
+            });
+        if result.is_ok() {
+            info.size = info.size.max(end as u32);
+        }
+        // Record the allocated clusters even on failure.
+        self.update_entry(&info)?;
+        result.map(|_| len)
+    }
+}
+
+impl Drop for FatNode {
+    fn drop(&mut self) {
+        let info = self.info.lock();
+        if info.unlinked && info.first_cluster != 0 {
+            self.fs.pending_free.lock().push(info.first_cluster);
+        }
+    }
+}
+
+impl Inode for FatNode {
+    fn stat(&self) -> Stat {
+        Stat {
+            kind: self.kind,
+            size: match self.kind {
+                FileKind::Directory => 0,
+                _ => self.info.lock().size as usize,
+            },
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.read(offset, buf.len(), |done, bytes| {
+            buf[done..done + bytes.len()].copy_from_slice(bytes);
+            Ok(())
+        })
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        self.write(offset, buf.len(), |done, bytes| {
+            bytes.copy_from_slice(&buf[done..done + bytes.len()]);
+            Ok(())
+        })
+    }
+
+    /// # Errors
+    /// - EFBIG: beyond 4GiB - 1
+    /// - ENOSPC: no free cluster
+    fn truncate(&self, len: usize) -> Result<(), Errno> {
+        if self.kind == FileKind::Directory {
+            return Err(Errno::EISDIR);
+        }
+        let new_size = u32::try_from(len).map_err(|_| Errno::EFBIG)?;
+        let fs = &self.fs;
+        let mut state = fs.lock();
+        let mut info = self.info.lock();
+        let size = info.size as usize;
+        let clusters = align_up(len, fs.bpb.cluster_bytes()) / fs.bpb.cluster_bytes();
+        if len < size {
+            info.first_cluster = fs.shrink_chain(&mut state, info.first_cluster, clusters)?;
+        } else if len > size {
+            let result = fs
+                .extend_chain(&mut state, &mut info.first_cluster, clusters)
+                .and_then(|()| {
+                    fs.write_data(info.first_cluster, size, len - size, |_, bytes| {
+                        bytes.fill(0);
+                        Ok(())
+                    })
+                });
+            if let Err(err) = result {
+                self.update_entry(&info)?;
+                return Err(err);
+            }
+        }
+        info.size = new_size;
+        self.update_entry(&info)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        let dir = self.dir_cluster()?;
+        let mut state = self.fs.lock();
+        let items = self.fs.read_dir(dir)?;
+        let item = (items.iter().find(|item| item.matches(name))).ok_or(Errno::ENOENT)?;
+        self.fs.check_item(item)?;
+        Ok(self.node(&mut state, item))
+    }
+
+    /// # Errors
+    /// - EEXIST: already exists
+    /// - EINVAL: invalid character in `name`
+    /// - ENAMETOOLONG: longer than 255 UTF-16 units
+    /// - ENOSPC: the directory can't be extended
+    fn create(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        let dir = self.dir_cluster()?;
+        let mut state = self.fs.lock();
+        let pos =
+            (self.fs).add_entry(&mut state, dir, name, None, short_entry(ATTR_ARCHIVE, 0, 0))?;
+        let node = Arc::new(FatNode::new(
+            self.fs.clone(),
+            FileKind::Regular,
+            NodeInfo {
+                first_cluster: 0,
+                size: 0,
+                entry: Some(pos),
+                unlinked: false,
+            },
+        ));
+        state.nodes.push(Arc::downgrade(&node));
+        Ok(node)
+    }
+
+    /// The new directory has `.` & `..`.
+    fn mkdir(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        let dir = self.dir_cluster()?;
+        let fs = &self.fs;
+        let mut state = fs.lock();
+        check_name(name)?;
+        let cluster = fs.alloc_cluster(&mut state, 0)?;
+        let dots = [
+            (*b".          ", cluster),
+            (*b"..         ", fs.parent_cluster(dir)),
+        ];
+        let result = (dots.iter().enumerate())
+            .try_for_each(|(index, (short, first))| {
+                let mut dot = short_entry(ATTR_DIRECTORY, *first, 0);
+                dot[..11].copy_from_slice(short);
+                fs.modify_entry(cluster, index as u32, |entry| entry.copy_from_slice(&dot))
+            })
+            .and_then(|_| {
+                let entry = short_entry(ATTR_DIRECTORY, cluster, 0);
+                fs.add_entry(&mut state, dir, name, None, entry)
+            });
+        let pos = match result {
+            Ok(pos) => pos,
+            Err(err) => {
+                fs.free_chain(&mut state, cluster)?;
+                return Err(err);
+            }
+        };
+        let node = Arc::new(FatNode::new(
+            fs.clone(),
+            FileKind::Directory,
+            NodeInfo {
+                first_cluster: cluster,
+                size: 0,
+                entry: Some(pos),
+                unlinked: false,
+            },
+        ));
+        state.nodes.push(Arc::downgrade(&node));
+        Ok(node)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        let dir = self.dir_cluster()?;
+        let mut state = self.fs.lock();
+        let items = self.fs.read_dir(dir)?;
+        let item = (items.iter().find(|item| item.matches(name))).ok_or(Errno::ENOENT)?;
+        if item.kind() == FileKind::Directory {
+            return Err(Errno::EISDIR);
+        }
+        self.fs.check_item(item)?;
+        self.fs.remove_entry(item.pos)?;
+        self.fs.release(&mut state, item)
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), Errno> {
+        let dir = self.dir_cluster()?;
+        let mut state = self.fs.lock();
+        let items = self.fs.read_dir(dir)?;
+        let item = (items.iter().find(|item| item.matches(name))).ok_or(Errno::ENOENT)?;
+        if item.kind() != FileKind::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+        self.fs.check_item(item)?;
+        if !self.fs.read_dir(item.first_cluster)?.is_empty() {
+            return Err(Errno::ENOTEMPTY);
+        }
+        self.fs.remove_entry(item.pos)?;
+        self.fs.release(&mut state, item)
+    }
+
+    /// The new entry is written before the old one is removed, so the file is never lost.
+    fn rename(&self, old_name: &str, new_dir: &dyn Inode, new_name: &str) -> Result<(), Errno> {
+        let new_dir = (new_dir.as_any())
+            .and_then(|any| any.downcast_ref::<FatNode>())
+            .filter(|dir| Arc::ptr_eq(&dir.fs, &self.fs))
+            .ok_or(Errno::EXDEV)?;
+        let (old_dir, new_dir) = (self.dir_cluster()?, new_dir.dir_cluster()?);
+        let fs = &self.fs;
+        let mut state = fs.lock();
+
+        let old_items = fs.read_dir(old_dir)?;
+        let old = (old_items.iter().find(|item| item.matches(old_name))).ok_or(Errno::ENOENT)?;
+        if old_dir == new_dir && old.name == new_name {
+            return Ok(());
+        }
+        fs.check_item(old)?;
+        // A directory into itself
+        if old.kind() == FileKind::Directory && is_in(fs, new_dir, old.first_cluster)? {
+            return Err(Errno::EINVAL);
+        }
+        let new_items = fs.read_dir(new_dir)?;
+        let target = (new_items.iter()).find(|item| item.matches(new_name) && item.pos != old.pos);
+        if let Some(target) = target {
+            fs.check_item(target)?;
+            match (old.kind(), target.kind()) {
+                (FileKind::Directory, FileKind::Directory)
+                    if !fs.read_dir(target.first_cluster)?.is_empty() =>
+                {
+                    return Err(Errno::ENOTEMPTY)
+                }
+                (FileKind::Directory, FileKind::Regular) => return Err(Errno::ENOTDIR),
+                (FileKind::Regular, FileKind::Directory) => return Err(Errno::EISDIR),
+                _ => {}
+            }
+            fs.remove_entry(target.pos)?;
+            fs.release(&mut state, target)?;
+        }
+
+        // Take the current data of an open file.
+        let open = fs.open_node(&mut state, old.pos);
+        let (first_cluster, size) = match &open {
+            Some(node) => {
+                let info = node.info.lock();
+                (info.first_cluster, info.size)
+            }
+            None => (old.first_cluster, old.size),
+        };
+        let entry = short_entry(old.attr, first_cluster, size);
+        let pos = fs.add_entry(&mut state, new_dir, new_name, Some(old.pos), entry)?;
+        fs.remove_entry(old.pos)?;
+        if let Some(node) = &open {
+            node.info.lock().entry = Some(pos);
+        }
+        if old.kind() == FileKind::Directory && old_dir != new_dir {
+            let parent = fs.parent_cluster(new_dir);
+            fs.modify_entry(old.first_cluster, 1, |entry| {
+                set_entry_data(entry, parent, 0)
+            })?;
+        }
+        Ok(())
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+        let dir = self.dir_cluster()?;
+        let _state = self.fs.lock();
+        let items = self.fs.read_dir(dir)?;
+        Ok(items.into_iter().nth(index).map(|item| DirEntry {
+            kind: item.kind(),
+            name: item.name,
+        }))
+    }
+
+    fn read_user(&self, offset: usize, buf: usize, len: usize) -> Result<usize, Errno> {
+        self.read(offset, len, |done, bytes| copy_to_user(buf + done, bytes))
+    }
+
+    fn write_user(&self, offset: usize, buf: usize, len: usize) -> Result<usize, Errno> {
+        self.write(offset, len, |done, bytes| copy_from_user(bytes, buf + done))
+    }
+}
+
+/// Is the directory `dir` the directory `ancestor` or under it?
+fn is_in(fs: &Fat32, mut dir: u32, ancestor: u32) -> Result<bool, Errno> {
+    // Bounded, in case of a loop
+    for _ in 0..fs.bpb.cluster_count {
+        if dir == ancestor {
+            return Ok(true);
+        }
+        if dir == fs.bpb.root_cluster {
+            return Ok(false);
+        }
+        // `..` is the 2nd entry.
+        let (sector, offset) = fs.entry_location(dir, 1)?;
+        let entry = &fs.read_sector(sector)?[offset..offset + ENTRY_SIZE];
+        dir = match (u16_at(entry, 20) as u32) << 16 | u16_at(entry, 26) as u32 {
+            0 => fs.bpb.root_cluster,
+            parent => parent,
+        };
+    }
+    Ok(false)
+}
+
+/// Collects the LFN entries before a 8.3 name entry.
+#[derive(Default)]
+struct LfnBuilder {
+    /// The units of each entry in the physical order (the end of the name first)
+    parts: Vec<[u16; LFN_UNITS]>,
+    checksum: u8,
+    /// `LDIR_Ord` of the next entry. 0 after the 1st part.
+    next_ord: u8,
+}
+
+impl LfnBuilder {
+    fn clear(&mut self) {
+        self.parts.clear();
+        self.next_ord = 0;
+    }
+
+    fn push(&mut self, entry: &[u8]) {
+        let ord = entry[0] & !LFN_LAST;
+        if entry[0] & LFN_LAST != 0 {
+            self.parts.clear();
+            self.checksum = entry[13];
+            self.next_ord = ord;
+        }
+        if ord == 0 || ord != self.next_ord || entry[13] != self.checksum {
+            // Orphan
+            self.clear();
+            return;
+        }
+        let mut units = [0u16; LFN_UNITS];
+        for (unit, offset) in units.iter_mut().zip(LFN_UNIT_OFFSETS) {
+            *unit = u16_at(entry, offset);
+        }
+        self.parts.push(units);
+        self.next_ord -= 1;
+    }
+
+    /// The long name for the 8.3 name `short`, & the LFN entry count.
+    fn take(&mut self, short: &[u8; 11]) -> (Option<String>, u32) {
+        let complete = !self.parts.is_empty() && self.next_ord == 0;
+        let result = match complete && self.checksum == checksum(short) {
+            true => {
+                let units = (self.parts.iter().rev().flatten())
+                    .copied()
+                    .take_while(|unit| *unit != 0);
+                let name = char::decode_utf16(units)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect();
+                (Some(name), self.parts.len() as u32)
+            }
+            false => (None, 0),
+        };
+        self.clear();
+        result
+    }
+}
+
+/// The LFN entries of `name` in the physical order
+fn lfn_entries(name: &str, checksum: u8) -> Vec<[u8; ENTRY_SIZE]> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.chunks(LFN_UNITS).count();
+    (0..count)
+        .rev()
+        .map(|i| {
+            let mut entry = [0u8; ENTRY_SIZE];
+            entry[0] = (i + 1) as u8 | if i + 1 == count { LFN_LAST } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+            for (j, offset) in LFN_UNIT_OFFSETS.iter().enumerate() {
+                // NUL terminated & padded with 0xFFFF
+                let unit = match (i * LFN_UNITS + j).cmp(&units.len()) {
+                    core::cmp::Ordering::Less => units[i * LFN_UNITS + j],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xFFFF,
+                };
+                put_u16(&mut entry, *offset, unit);
+            }
+            entry
+        })
+        .collect()
+}
+
+/// `LDIR_Chksum` of the 8.3 name
+fn checksum(short: &[u8; 11]) -> u8 {
+    (short.iter()).fold(0u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// The 8.3 name for `name` in a directory of `items`. `name` itself if it is a valid 8.3 name,
+/// else a numbered alias like `LONGNA~1.TXT`.
+///
+/// # Return
+/// (8.3 name, the long name entries are needed)
+fn short_name(name: &str, items: &[DirItem]) -> ([u8; 11], bool) {
+    let upper = name.to_ascii_uppercase();
+    let (base, ext) = match upper.rfind('.') {
+        Some(dot) if dot > 0 => (&upper[..dot], &upper[dot + 1..]),
+        _ => (upper.as_str(), ""),
+    };
+    let exists = |short: &[u8; 11]| items.iter().any(|item| &item.short == short);
+
+    let valid = |part: &str, max| part.len() <= max && part.bytes().all(is_short_char);
+    if !base.is_empty() && valid(base, 8) && valid(ext, 3) {
+        let short = pack_short(base.as_bytes(), ext.as_bytes());
+        if !exists(&short) {
+            return (short, upper != name);
+        }
+    }
+
+    let clean = |part: &str| -> Vec<u8> {
+        (part.bytes())
+            .filter(|byte| !matches!(byte, b' ' | b'.'))
+            .map(|byte| if is_short_char(byte) { byte } else { b'_' })
+            .collect()
+    };
+    let (base, ext) = (clean(base), clean(ext));
+    let short = (1u32..)
+        .map(|n| {
+            let tail = format!("~{}", n);
+            let mut alias = base[..base.len().min(8 - tail.len())].to_vec();
+            alias.extend_from_slice(tail.as_bytes());
+            pack_short(&alias, &ext[..ext.len().min(3)])
+        })
+        .find(|short| !exists(short))
+        .unwrap();
+    (short, true)
+}
+
+/// Space padded 8.3 name
+fn pack_short(base: &[u8], ext: &[u8]) -> [u8; 11] {
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base);
+    short[8..8 + ext.len()].copy_from_slice(ext);
+    short
+}
+
+/// `NAME.EXT` of the 8.3 name. (`nt_res`: the lower case flags)
+fn short_to_string(short: &[u8; 11], nt_res: u8) -> String {
+    let part = |bytes: &[u8], lower: bool| -> String {
+        (bytes.iter().enumerate())
+            .map(|(i, byte)| match (i, *byte) {
+                (0, ENTRY_KANJI_E5) => '_',
+                (_, byte) if byte.is_ascii() && lower => byte.to_ascii_lowercase() as char,
+                (_, byte) if byte.is_ascii() => byte as char,
+                // OEM code page
+                _ => '_',
+            })
+            .collect::<String>()
+            .trim_end_matches(' ')
+            .into()
+    };
+    let base = part(&short[..8], nt_res & NT_LOWER_BASE != 0);
+    let ext = part(&short[8..], nt_res & NT_LOWER_EXT != 0);
+    match ext.is_empty() {
+        true => base,
+        false => format!("{}.{}", base, ext),
+    }
+}
+
+/// Can the byte be in a 8.3 name? (upper case)
+fn is_short_char(byte: u8) -> bool {
+    byte.is_ascii_uppercase() || byte.is_ascii_digit() || b"!#$%&'()-@^_`{}~".contains(&byte)
+}
+
+/// # Errors
+/// - EEXIST: `.` or `..` (resolved by the vfs, so they always exist)
+/// - EINVAL: a character FAT doesn't allow
+/// - ENAMETOOLONG: longer than `LFN_MAX` UTF-16 units
+fn check_name(name: &str) -> Result<(), Errno> {
+    match name {
+        "" | "." | ".." => Err(Errno::EEXIST),
+        name if name.encode_utf16().count() > LFN_MAX => Err(Errno::ENAMETOOLONG),
+        name if (name.chars()).any(|c| c.is_ascii_control() || "\"*/:<>?\\|".contains(c)) => {
+            Err(Errno::EINVAL)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A 8.3 name entry without the name
+fn short_entry(attr: u8, first_cluster: u32, size: u32) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[..11].fill(b' ');
+    entry[11] = attr;
+    // Creation, access & write dates
+    for offset in [16, 18, 24] {
+        put_u16(&mut entry, offset, DATE_EPOCH);
+    }
+    set_entry_data(&mut entry, first_cluster, size);
+    entry
+}
+
+/// Set the 1st cluster & size of a 8.3 name entry.
+fn set_entry_data(entry: &mut [u8], first_cluster: u32, size: u32) {
+    put_u16(entry, 20, (first_cluster >> 16) as u16);
+    put_u16(entry, 26, first_cluster as u16);
+    put_u32(entry, 28, size);
+}
+
+fn io_error(err: BlockError) -> Errno {
+    match err {
+        BlockError::ReadOnly => Errno::EROFS,
+        err => {
+            warn!("fat32: {}", err);
+            Errno::EIO
+        }
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn put_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
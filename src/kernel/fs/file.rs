@@ -0,0 +1,205 @@
+//! Open files & the per process file descriptor table.
+use super::devfs;
+use super::vfs::{DirEntry, Inode};
+use crate::proc::with_current_proc;
+use abi::fs::{FileKind, OpenFlags, Stat, SEEK_CUR, SEEK_END, SEEK_SET};
+use abi::Errno;
+use alloc::sync::Arc;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Max open files per process
+pub const FDS_MAX: usize = 16;
+
+/// An inode opened with the access mode & the offset.
+pub struct OpenFile {
+    inode: Arc<dyn Inode>,
+    flags: OpenFlags,
+    /// Bytes for files, entries for directories
+    offset: AtomicUsize,
+}
+
+impl OpenFile {
+    pub fn new(inode: Arc<dyn Inode>, flags: OpenFlags) -> Self {
+        Self {
+            inode,
+            flags,
+            offset: AtomicUsize::new(0),
+        }
+    }
+
+    /// Read to the user buffer `[buf, buf + len)` & advance the offset.
+    ///
+    /// The offset is not locked while reading, because reading the console sleeps.
+    /// (A file is shared only by the fds of one process.)
+    ///
+    /// # Errors
+    /// - Not opened for reading: EBADF
+    /// - Errors of [`Inode::read_user`]
+    pub fn read(&self, buf: usize, len: usize) -> Result<usize, Errno> {
+        if !self.flags.readable() {
+            return Err(Errno::EBADF);
+        }
+        let offset = self.offset.load(Ordering::Relaxed);
+        let read = self.inode.read_user(offset, buf, len)?;
+        self.offset.store(offset + read, Ordering::Relaxed);
+        Ok(read)
+    }
+
+    /// Write the user buffer `[buf, buf + len)` & advance the offset.
+    ///
+    /// With `APPEND`, the offset is moved to the end before writing.
+    ///
+    /// # Errors
+    /// - Not opened for writing: EBADF
+    /// - Errors of [`Inode::write_user`]
+    pub fn write(&self, buf: usize, len: usize) -> Result<usize, Errno> {
+        if !self.flags.writable() {
+            return Err(Errno::EBADF);
+        }
+        let offset = match self.flags.contains(OpenFlags::APPEND) {
+            true => self.inode.stat().size,
+            false => self.offset.load(Ordering::Relaxed),
+        };
+        let written = self.inode.write_user(offset, buf, len)?;
+        self.offset.store(offset + written, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    /// Move the offset. (see `abi::Syscalls::lseek`)
+    pub fn seek(&self, offset: isize, whence: u32) -> Result<usize, Errno> {
+        let stat = self.inode.stat();
+        if stat.kind == FileKind::CharDevice {
+            return Err(Errno::ESPIPE);
+        }
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => self.offset.load(Ordering::Relaxed),
+            SEEK_END => stat.size,
+            _ => return Err(Errno::EINVAL),
+        };
+        let new_offset = (base as isize)
+            .checked_add(offset)
+            .filter(|offset| *offset >= 0)
+            .ok_or(Errno::EINVAL)? as usize;
+        self.offset.store(new_offset, Ordering::Relaxed);
+        Ok(new_offset)
+    }
+
+    pub fn stat(&self) -> Stat {
+        self.inode.stat()
+    }
+
+    /// Read the next directory entry.
+    ///
+    /// # Return
+    /// `None` at the end of the directory.
+    pub fn readdir(&self) -> Result<Option<DirEntry>, Errno> {
+        let index = self.offset.load(Ordering::Relaxed);
+        let entry = self.inode.readdir(index)?;
+        if entry.is_some() {
+            self.offset.store(index + 1, Ordering::Relaxed);
+        }
+        Ok(entry)
+    }
+
+    pub fn ioctl(&self, request: usize, arg: usize) -> Result<usize, Errno> {
+        self.inode.ioctl(request, arg)
+    }
+}
+
+/// `OpenFile { flags: OpenFlags(RDWR), offset: 0 }`
+impl fmt::Debug for OpenFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenFile")
+            .field("flags", &self.flags)
+            .field("offset", &self.offset.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+/// fd -> open file. The same open file can be at several fds. (e.g. stdout & stderr)
+#[derive(Clone)]
+pub struct FdTable {
+    files: [Option<Arc<OpenFile>>; FDS_MAX],
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        const CLOSED: Option<Arc<OpenFile>> = None;
+        Self {
+            files: [CLOSED; FDS_MAX],
+        }
+    }
+
+    /// fds 0(stdin), 1(stdout) & 2(stderr) on the console
+    pub fn stdio() -> Self {
+        let console = Arc::new(OpenFile::new(devfs::console(), OpenFlags::RDWR));
+        let mut table = Self::new();
+        table.files[..3].fill(Some(console));
+        table
+    }
+
+    /// # Errors
+    /// Not opened: EBADF
+    pub fn get(&self, fd: usize) -> Result<Arc<OpenFile>, Errno> {
+        self.files
+            .get(fd)
+            .and_then(|file| file.clone())
+            .ok_or(Errno::EBADF)
+    }
+
+    /// Put `file` at the lowest free fd.
+    ///
+    /// # Errors
+    /// No free fd: EMFILE
+    pub fn insert(&mut self, file: Arc<OpenFile>) -> Result<usize, Errno> {
+        let fd = self
+            .files
+            .iter()
+            .position(|file| file.is_none())
+            .ok_or(Errno::EMFILE)?;
+        self.files[fd] = Some(file);
+        Ok(fd)
+    }
+
+    /// # Errors
+    /// Not opened: EBADF
+    pub fn remove(&mut self, fd: usize) -> Result<Arc<OpenFile>, Errno> {
+        self.files
+            .get_mut(fd)
+            .and_then(|file| file.take())
+            .ok_or(Errno::EBADF)
+    }
+
+    /// Close all fds.
+    pub fn clear(&mut self) {
+        self.files.fill(None);
+    }
+}
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Open fds only: `{0: OpenFile { .. }, 1: ..}`
+impl fmt::Debug for FdTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                (self.files.iter().enumerate())
+                    .filter_map(|(fd, file)| file.as_ref().map(|file| (fd, file))),
+            )
+            .finish()
+    }
+}
+
+/// The open file of `fd` of the running process.
+///
+/// # Errors
+/// Not opened: EBADF
+pub fn current_file(fd: usize) -> Result<Arc<OpenFile>, Errno> {
+    with_current_proc(|proc| proc.fds().get(fd))
+}
@@ -0,0 +1,55 @@
+//! File systems
+//!
+//! The system calls access every file system through the [`vfs`].
+//!
+//! | path   | file system                                                       |
+//! |--------|-------------------------------------------------------------------|
+//! | `/`    | the root disk ([`fat32`] or [`tar`]), or [`tmpfs`] without a disk |
+//! | `/tmp` | [`tmpfs`]                                                         |
+//! | `/dev` | devices ([`devfs`])                                               |
+pub mod devfs;
+pub mod fat32;
+pub mod file;
+pub mod tar;
+pub mod tmpfs;
+pub mod vfs;
+
+pub use file::{current_file, FdTable, OpenFile};
+pub use vfs::{mkdir, mount, open, rename, rmdir, unlink, DirEntry, Inode, PATH_MAX};
+
+use crate::{block, warn};
+use alloc::sync::Arc;
+
+/// Load the file systems & mount them. (after the block drivers)
+pub fn init() {
+    match disk_root() {
+        Some(root) => {
+            mount("/", root);
+            mount("/tmp", tmpfs::new());
+        }
+        None => {
+            // `/tmp` is just a directory of the root.
+            let root = tmpfs::new();
+            root.mkdir("tmp").expect("tmpfs: failed to create /tmp");
+            mount("/", root);
+        }
+    }
+    mount("/dev", devfs::root());
+}
+
+/// The root directory of the root disk: FAT32 if the disk is formatted so, else tar.
+fn disk_root() -> Option<Arc<dyn Inode>> {
+    match block::root_device() {
+        Some(device) if fat32::detect(device) => match fat32::mount(device) {
+            Ok(root) => Some(root),
+            Err(err) => {
+                warn!("fat32: failed to mount: {}", err);
+                None
+            }
+        },
+        _ => {
+            tar::init();
+            tar::root()
+        }
+    }
+}
@@ -0,0 +1,488 @@
+//! tar(ustar) file system on the root disk.
+//!
+//! Like chapter 17 of the book, the whole archive is loaded into memory at boot, and every write
+//! rewrites the archive to the disk. Only the regular files at the top are supported.
+//!
+//! | offset | size | field                               |
+//! |--------|------|-------------------------------------|
+//! | 0      | 100  | name (NUL terminated)               |
+//! | 124    | 12   | size (octal)                        |
+//! | 148    | 8    | checksum (octal, header byte sum)   |
+//! | 156    | 1    | type (`'0'`: regular file)          |
+//! | 257    | 6    | magic `"ustar\0"`                   |
+//! | 263    | 2    | version `"00"`                      |
+//!
+//! The file data follows the 512 bytes header, padded to the 512 bytes boundary.
+//! The archive ends with 2 zero blocks.
+//! The GNU format (magic & version `"ustar  \0"`, written by GNU tar by default) is also read.
+//! - ref: https://www.gnu.org/software/tar/manual/html_node/Standard.html
+use super::vfs::{DirEntry, Inode};
+use crate::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::uaccess::{copy_from_user, copy_to_user};
+use crate::{info, warn};
+use abi::fs::{FileKind, Stat};
+use abi::Errno;
+use alloc::{string::String, sync::Arc, vec};
+use kernel::addr::align_up;
+use kernel::spinlock::SpinLock;
+
+const FILES_MAX: usize = 8;
+/// Max bytes per file
+pub const FILE_DATA_MAX: usize = 4096;
+/// Max bytes of the file name (without NUL)
+pub const NAME_MAX: usize = 99;
+
+const MAGIC: &[u8] = b"ustar\0";
+/// Magic & version of the GNU format (the default of GNU tar)
+const GNU_MAGIC: &[u8] = b"ustar  \0";
+const TYPE_REGULAR: u8 = b'0';
+
+struct File {
+    in_use: bool,
+    name: [u8; NAME_MAX + 1],
+    name_len: usize,
+    data: [u8; FILE_DATA_MAX],
+    size: usize,
+}
+
+impl File {
+    const UNUSED: Self = Self {
+        in_use: false,
+        name: [0; NAME_MAX + 1],
+        name_len: 0,
+        data: [0; FILE_DATA_MAX],
+        size: 0,
+    };
+
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    /// Sectors of the header & data
+    fn sectors(&self) -> u64 {
+        1 + data_sectors(self.size)
+    }
+}
+
+struct TarFs {
+    device: Option<&'static dyn BlockDevice>,
+    files: [File; FILES_MAX],
+    /// A file in the archive couldn't be loaded. Writing back would lose it.
+    read_only: bool,
+}
+
+static FS: SpinLock<TarFs> = SpinLock::new(TarFs {
+    device: None,
+    files: [File::UNUSED; FILES_MAX],
+    read_only: false,
+});
+
+/// Load the archive of the root disk. (after the block drivers)
+///
+/// Mounted as the root by [`super::init`].
+pub fn init() {
+    let Some(device) = block::root_device() else {
+        info!("tarfs: no disk");
+        return;
+    };
+    let mut fs = FS.lock();
+    let fs = &mut *fs;
+    fs.device = Some(device);
+
+    let mut header = [0u8; SECTOR_SIZE];
+    let mut sector = 0;
+    while sector < device.sector_count() {
+        if let Err(err) = device.read_sector(sector, &mut header) {
+            warn!("tarfs: {}", err);
+            fs.read_only = true;
+            break;
+        }
+        // End of archive
+        if header[0] == 0 {
+            break;
+        }
+        let magic_ok = &header[257..263] == MAGIC || &header[257..265] == GNU_MAGIC;
+        if !magic_ok || parse_octal(&header[148..156]) != Some(checksum(&header)) {
+            warn!("tarfs: invalid header at sector {}", sector);
+            fs.read_only = true;
+            break;
+        }
+
+        let size = parse_octal(&header[124..136]).unwrap_or(0);
+        let name_len = header[..=NAME_MAX]
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(0);
+        let name = core::str::from_utf8(&header[..name_len]).unwrap_or("?");
+        let is_regular = matches!(header[156], TYPE_REGULAR | 0);
+        match fs.files.iter_mut().find(|file| !file.in_use) {
+            Some(file) if is_regular && size <= FILE_DATA_MAX && name_len != 0 => {
+                file.name[..name_len].copy_from_slice(&header[..name_len]);
+                file.name_len = name_len;
+                file.size = size;
+                let mut buf = [0u8; SECTOR_SIZE];
+                let mut loaded = true;
+                for (i, chunk) in file.data[..size].chunks_mut(SECTOR_SIZE).enumerate() {
+                    if device.read_sector(sector + 1 + i as u64, &mut buf).is_err() {
+                        warn!("tarfs: failed to read {}", name);
+                        fs.read_only = true;
+                        loaded = false;
+                        break;
+                    }
+                    chunk.copy_from_slice(&buf[..chunk.len()]);
+                }
+                // A partly read file is skipped. (The slot is reused.)
+                file.in_use = loaded;
+            }
+            _ => {
+                warn!("tarfs: unsupported entry {} (read only)", name);
+                fs.read_only = true;
+            }
+        }
+        sector += 1 + data_sectors(size);
+    }
+
+    let count = fs.files.iter().filter(|file| file.in_use).count();
+    info!(
+        "tarfs: {} files{}",
+        count,
+        if fs.read_only { " (read only)" } else { "" }
+    );
+}
+
+/// The root directory. `None` if there is no disk.
+pub fn root() -> Option<Arc<dyn Inode>> {
+    FS.lock().device?;
+    Some(Arc::new(TarDir))
+}
+
+/// The top directory of the archive
+struct TarDir;
+
+impl Inode for TarDir {
+    fn stat(&self) -> Stat {
+        Stat {
+            kind: FileKind::Directory,
+            size: 0,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        let fs = FS.lock();
+        let index = fs.find(name).ok_or(Errno::ENOENT)?;
+        Ok(Arc::new(TarFile { index }))
+    }
+
+    /// # Errors
+    /// - EROFS: some entries couldn't be loaded
+    /// - ENAMETOOLONG: `name` is longer than `NAME_MAX`
+    /// - EEXIST: already exists
+    /// - ENOSPC: no free file slot or disk space
+    /// - EIO: the disk failed
+    fn create(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        let mut fs = FS.lock();
+        fs.writable()?;
+        if name.len() > NAME_MAX {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        if fs.find(name).is_some() {
+            return Err(Errno::EEXIST);
+        }
+        let index = (fs.files.iter().position(|file| !file.in_use)).ok_or(Errno::ENOSPC)?;
+        fs.check_space(index, 0)?;
+        let file = &mut fs.files[index];
+        file.name[..name.len()].copy_from_slice(name.as_bytes());
+        file.name_len = name.len();
+        file.size = 0;
+        file.in_use = true;
+        if let Err(err) = fs.flush() {
+            fs.files[index].in_use = false;
+            return Err(err);
+        }
+        Ok(Arc::new(TarFile { index }))
+    }
+
+    /// Only the regular files at the top are supported.
+    fn mkdir(&self, _name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::EPERM)
+    }
+
+    /// Files are never removed. (The open files refer to the slot.)
+    fn unlink(&self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn rmdir(&self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn rename(&self, _old_name: &str, _new_dir: &dyn Inode, _new_name: &str) -> Result<(), Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+        let fs = FS.lock();
+        let entry = fs.files.iter().filter(|file| file.in_use).nth(index);
+        Ok(entry.map(|file| DirEntry {
+            name: String::from_utf8_lossy(file.name()).into_owned(),
+            kind: FileKind::Regular,
+        }))
+    }
+}
+
+/// A regular file. (Files are never removed, so the slot index is stable.)
+struct TarFile {
+    index: usize,
+}
+
+impl TarFile {
+    /// Call `f` with the content from `offset`.
+    fn read(
+        &self,
+        offset: usize,
+        f: impl FnOnce(&[u8]) -> Result<usize, Errno>,
+    ) -> Result<usize, Errno> {
+        let fs = FS.lock();
+        let file = &fs.files[self.index];
+        f(&file.data[offset.min(file.size)..file.size])
+    }
+
+    /// Replace `[offset, offset + len)` by `fill`(writes `len` bytes to the given buffer), and
+    /// write the archive back to the disk. On error, the file is not changed.
+    ///
+    /// # Errors
+    /// - EROFS: some entries couldn't be loaded
+    /// - EFBIG: the end is beyond `FILE_DATA_MAX`
+    /// - ENOSPC: no disk space
+    /// - EIO: the disk failed
+    /// - the error of `fill`
+    fn write(
+        &self,
+        offset: usize,
+        len: usize,
+        fill: impl FnOnce(&mut [u8]) -> Result<(), Errno>,
+    ) -> Result<usize, Errno> {
+        if len == 0 {
+            return Ok(0);
+        }
+        let end = (offset.checked_add(len))
+            .filter(|end| *end <= FILE_DATA_MAX)
+            .ok_or(Errno::EFBIG)?;
+        // Staged, so that a failing `fill`(e.g. a fault in the user buffer) changes nothing.
+        let mut staged = vec![0u8; len];
+        fill(&mut staged)?;
+
+        let mut fs = FS.lock();
+        fs.writable()?;
+        let old_size = fs.files[self.index].size;
+        let size = old_size.max(end);
+        fs.check_space(self.index, size)?;
+
+        let file = &mut fs.files[self.index];
+        let old_data = file.data[offset..end].to_vec();
+        if offset > file.size {
+            file.data[file.size..offset].fill(0);
+        }
+        file.data[offset..end].copy_from_slice(&staged);
+        file.size = size;
+        if let Err(err) = fs.flush() {
+            // Keep the memory same as the disk. (The bytes beyond the old size are not used.)
+            let file = &mut fs.files[self.index];
+            file.data[offset..end].copy_from_slice(&old_data);
+            file.size = old_size;
+            return Err(err);
+        }
+        Ok(len)
+    }
+}
+
+impl Inode for TarFile {
+    fn stat(&self) -> Stat {
+        Stat {
+            kind: FileKind::Regular,
+            size: FS.lock().files[self.index].size,
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.read(offset, |data| {
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok(len)
+        })
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        self.write(offset, buf.len(), |data| {
+            data.copy_from_slice(buf);
+            Ok(())
+        })
+    }
+
+    fn truncate(&self, len: usize) -> Result<(), Errno> {
+        let mut fs = FS.lock();
+        fs.writable()?;
+        if len > FILE_DATA_MAX {
+            return Err(Errno::EFBIG);
+        }
+        fs.check_space(self.index, len)?;
+        let file = &mut fs.files[self.index];
+        let old_size = file.size;
+        if len > file.size {
+            file.data[file.size..len].fill(0);
+        }
+        file.size = len;
+        if let Err(err) = fs.flush() {
+            fs.files[self.index].size = old_size;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Copy directly from the file data.
+    fn read_user(&self, offset: usize, buf: usize, len: usize) -> Result<usize, Errno> {
+        self.read(offset, |data| {
+            let len = data.len().min(len);
+            copy_to_user(buf, &data[..len]).map(|_| len)
+        })
+    }
+
+    /// Copy to the file data via a staging buffer, and write the disk once.
+    fn write_user(&self, offset: usize, buf: usize, len: usize) -> Result<usize, Errno> {
+        self.write(offset, len, |data| copy_from_user(data, buf))
+    }
+}
+
+impl TarFs {
+    /// Index of the file `name`
+    fn find(&self, name: &str) -> Option<usize> {
+        self.files
+            .iter()
+            .position(|file| file.in_use && file.name() == name.as_bytes())
+    }
+
+    /// # Errors
+    /// - ENODEV: no disk
+    /// - EROFS: some entries couldn't be loaded
+    fn writable(&self) -> Result<(), Errno> {
+        self.device.ok_or(Errno::ENODEV)?;
+        match self.read_only {
+            true => Err(Errno::EROFS),
+            false => Ok(()),
+        }
+    }
+
+    /// Check that the archive fits the disk when the file `index` is `size` bytes.
+    ///
+    /// # Errors
+    /// ENOSPC
+    fn check_space(&self, index: usize, size: usize) -> Result<(), Errno> {
+        // The other files + this file + end of archive
+        let sectors: u64 = (self.files.iter().enumerate())
+            .filter(|(i, file)| file.in_use && *i != index)
+            .map(|(_, file)| file.sectors())
+            .sum::<u64>()
+            + 1
+            + data_sectors(size)
+            + 2;
+        match self.device {
+            Some(device) if sectors <= device.sector_count() => Ok(()),
+            _ => Err(Errno::ENOSPC),
+        }
+    }
+
+    /// Write the archive back to the disk.
+    ///
+    /// # Errors
+    /// - ENODEV: no disk
+    /// - EIO: the disk failed
+    fn flush(&self) -> Result<(), Errno> {
+        let device = self.device.ok_or(Errno::ENODEV)?;
+        flush(&self.files, device).map_err(|err| {
+            warn!("tarfs: {}", err);
+            Errno::EIO
+        })
+    }
+}
+
+/// Write all files as a tar archive from the sector 0.
+fn flush(files: &[File], device: &dyn BlockDevice) -> Result<(), block::BlockError> {
+    let mut sector = 0;
+    for file in files.iter().filter(|file| file.in_use) {
+        device.write_sector(sector, &header(file))?;
+        sector += 1;
+        for chunk in file.data[..file.size].chunks(SECTOR_SIZE) {
+            let mut buf = [0u8; SECTOR_SIZE];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            device.write_sector(sector, &buf)?;
+            sector += 1;
+        }
+    }
+    for _ in 0..2 {
+        device.write_sector(sector, &[0; SECTOR_SIZE])?;
+        sector += 1;
+    }
+    Ok(())
+}
+
+/// Sectors of `size` bytes data
+fn data_sectors(size: usize) -> u64 {
+    (align_up(size, SECTOR_SIZE) / SECTOR_SIZE) as u64
+}
+
+/// ustar header of the regular file
+fn header(file: &File) -> [u8; SECTOR_SIZE] {
+    let mut header = [0u8; SECTOR_SIZE];
+    header[..file.name_len].copy_from_slice(file.name());
+    put_octal(&mut header[100..108], 0o644); // mode
+    put_octal(&mut header[108..116], 0); // uid
+    put_octal(&mut header[116..124], 0); // gid
+    put_octal(&mut header[124..136], file.size);
+    put_octal(&mut header[136..148], 0); // mtime
+    header[156] = TYPE_REGULAR;
+    header[257..263].copy_from_slice(MAGIC);
+    header[263..265].copy_from_slice(b"00"); // version
+    let checksum = checksum(&header);
+    // 6 digits, NUL, space
+    put_octal(&mut header[148..155], checksum);
+    header[155] = b' ';
+    header
+}
+
+/// Sum of the header bytes, where the checksum field is spaces.
+fn checksum(header: &[u8; SECTOR_SIZE]) -> usize {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| match i {
+            148..=155 => b' ' as usize,
+            _ => *byte as usize,
+        })
+        .sum()
+}
+
+/// Zero padded octal digits & NUL
+fn put_octal(field: &mut [u8], mut value: usize) {
+    let (nul, digits) = field.split_last_mut().unwrap();
+    *nul = 0;
+    for digit in digits.iter_mut().rev() {
+        *digit = b'0' + (value % 8) as u8;
+        value /= 8;
+    }
+}
+
+/// Octal digits terminated by NUL or space. (Leading spaces are skipped.)
+fn parse_octal(field: &[u8]) -> Option<usize> {
+    let digits = field
+        .iter()
+        .skip_while(|byte| **byte == b' ')
+        .take_while(|byte| !matches!(byte, 0 | b' '));
+    let mut value = 0usize;
+    for byte in digits {
+        match byte {
+            b'0'..=b'7' => value = value.checked_mul(8)? + (byte - b'0') as usize,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
@@ -0,0 +1,367 @@
+//! tmpfs: in-memory file system with directories.
+//!
+//! File data is kept in pages of [`alloc_page`], allocated as the file grows. The pages are
+//! freed when the file is truncated, or unlinked & its last open file is closed.
+//! One tmpfs can use up to `PAGES_MAX` pages.
+//!
+//! The bytes after the end of a file in its last page are always 0, so a file can be extended
+//! without clearing.
+use super::vfs::{DirEntry, Inode};
+use crate::pages::{alloc_page, free_page, PAGE_SIZE};
+use abi::fs::{FileKind, Stat, NAME_MAX};
+use abi::Errno;
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kernel::addr::{align_up, is_aligned};
+use kernel::spinlock::SpinLock;
+
+/// Max pages of one tmpfs (1MiB)
+const PAGES_MAX: usize = 256;
+
+/// Shared by all inodes of one tmpfs
+struct TmpFs {
+    pages_used: AtomicUsize,
+}
+
+impl TmpFs {
+    /// # Errors
+    /// ENOSPC: `PAGES_MAX` pages are used, or out of memory
+    fn alloc_page(&self) -> Result<usize, Errno> {
+        if self.pages_used.load(Ordering::Relaxed) >= PAGES_MAX {
+            return Err(Errno::ENOSPC);
+        }
+        let paddr = alloc_page().ok_or(Errno::ENOSPC)?;
+        self.pages_used.fetch_add(1, Ordering::Relaxed);
+        Ok(paddr.into())
+    }
+
+    /// Allocate `pages` up to `len` bytes. On failure, the pages allocated by this are freed.
+    ///
+    /// # Errors
+    /// ENOSPC
+    fn grow(&self, pages: &mut Vec<usize>, len: usize) -> Result<(), Errno> {
+        let old_len = pages.len();
+        while pages.len() * PAGE_SIZE < len {
+            match self.alloc_page() {
+                Ok(page) => pages.push(page),
+                Err(err) => {
+                    for page in pages.drain(old_len..) {
+                        unsafe { self.free_page(page) };
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// # Safety
+    /// `paddr` is allocated by [`TmpFs::alloc_page`], and never used after this.
+    unsafe fn free_page(&self, paddr: usize) {
+        free_page(paddr.into());
+        self.pages_used.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+enum Node {
+    /// (name, inode) in the creation order
+    Dir(Vec<(String, Arc<TmpInode>)>),
+    File {
+        /// Physical(= kernel virtual) address of each page
+        pages: Vec<usize>,
+        size: usize,
+    },
+}
+
+struct TmpInode {
+    fs: Arc<TmpFs>,
+    node: SpinLock<Node>,
+}
+
+/// A new empty tmpfs. (The root directory)
+pub fn new() -> Arc<dyn Inode> {
+    let fs = Arc::new(TmpFs {
+        pages_used: AtomicUsize::new(0),
+    });
+    Arc::new(TmpInode::new(fs, Node::Dir(Vec::new())))
+}
+
+impl TmpInode {
+    fn new(fs: Arc<TmpFs>, node: Node) -> Self {
+        Self {
+            fs,
+            node: SpinLock::new(node),
+        }
+    }
+
+    fn kind(&self) -> FileKind {
+        match *self.node.lock() {
+            Node::Dir(_) => FileKind::Directory,
+            Node::File { .. } => FileKind::Regular,
+        }
+    }
+
+    fn is_empty_dir(&self) -> bool {
+        matches!(&*self.node.lock(), Node::Dir(entries) if entries.is_empty())
+    }
+
+    /// Add the entry `name` of `node` to this directory.
+    ///
+    /// # Errors
+    /// - ENOTDIR: not a directory
+    /// - ENAMETOOLONG: `name` is longer than `NAME_MAX`
+    /// - EEXIST: already exists
+    fn add(&self, name: &str, node: Node) -> Result<Arc<dyn Inode>, Errno> {
+        check_name(name)?;
+        let mut dir = self.node.lock();
+        let entries = dir_entries(&mut dir)?;
+        if find(entries, name).is_some() {
+            return Err(Errno::EEXIST);
+        }
+        let inode = Arc::new(TmpInode::new(self.fs.clone(), node));
+        entries.push((String::from(name), inode.clone()));
+        Ok(inode)
+    }
+
+    /// Remove the entry `name` of this directory, if `check` accepts it.
+    fn remove(
+        &self,
+        name: &str,
+        check: impl FnOnce(&TmpInode) -> Result<(), Errno>,
+    ) -> Result<(), Errno> {
+        let mut dir = self.node.lock();
+        let entries = dir_entries(&mut dir)?;
+        let index = find(entries, name).ok_or(Errno::ENOENT)?;
+        check(&entries[index].1)?;
+        entries.remove(index);
+        Ok(())
+    }
+}
+
+impl Drop for TmpInode {
+    fn drop(&mut self) {
+        if let Node::File { pages, .. } = &mut *self.node.lock() {
+            for page in pages.drain(..) {
+                unsafe { self.fs.free_page(page) };
+            }
+        }
+    }
+}
+
+impl Inode for TmpInode {
+    fn stat(&self) -> Stat {
+        match *self.node.lock() {
+            Node::Dir(_) => Stat {
+                kind: FileKind::Directory,
+                size: 0,
+            },
+            Node::File { size, .. } => Stat {
+                kind: FileKind::Regular,
+                size,
+            },
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        let node = self.node.lock();
+        let Node::File { pages, size } = &*node else {
+            return Err(Errno::EISDIR);
+        };
+        let len = buf.len().min(size.saturating_sub(offset));
+        let mut read = 0;
+        while read < len {
+            let pos = offset + read;
+            let n = (PAGE_SIZE - pos % PAGE_SIZE).min(len - read);
+            buf[read..read + n]
+                .copy_from_slice(&page(pages[pos / PAGE_SIZE])[pos % PAGE_SIZE..][..n]);
+            read += n;
+        }
+        Ok(len)
+    }
+
+    /// # Errors
+    /// - EFBIG: the end overflows
+    /// - ENOSPC: no more pages (nothing is written or allocated)
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let end = offset.checked_add(buf.len()).ok_or(Errno::EFBIG)?;
+        let mut node = self.node.lock();
+        let Node::File { pages, size } = &mut *node else {
+            return Err(Errno::EISDIR);
+        };
+        // The pages of the gap are also allocated, to keep reads simple.
+        self.fs.grow(pages, end)?;
+        let mut written = 0;
+        while written < buf.len() {
+            let pos = offset + written;
+            let n = (PAGE_SIZE - pos % PAGE_SIZE).min(buf.len() - written);
+            page(pages[pos / PAGE_SIZE])[pos % PAGE_SIZE..][..n]
+                .copy_from_slice(&buf[written..written + n]);
+            written += n;
+        }
+        *size = (*size).max(end);
+        Ok(written)
+    }
+
+    fn truncate(&self, len: usize) -> Result<(), Errno> {
+        let mut node = self.node.lock();
+        let Node::File { pages, size } = &mut *node else {
+            return Err(Errno::EISDIR);
+        };
+        if len < *size {
+            // Keep the bytes after the end 0.
+            if !is_aligned(len, PAGE_SIZE) {
+                page(pages[len / PAGE_SIZE])[len % PAGE_SIZE..].fill(0);
+            }
+            for page in pages.drain(align_up(len, PAGE_SIZE) / PAGE_SIZE..) {
+                unsafe { self.fs.free_page(page) };
+            }
+        }
+        self.fs.grow(pages, len)?;
+        *size = len;
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        let mut dir = self.node.lock();
+        let entries = dir_entries(&mut dir)?;
+        let index = find(entries, name).ok_or(Errno::ENOENT)?;
+        Ok(entries[index].1.clone())
+    }
+
+    fn create(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        self.add(
+            name,
+            Node::File {
+                pages: Vec::new(),
+                size: 0,
+            },
+        )
+    }
+
+    fn mkdir(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        self.add(name, Node::Dir(Vec::new()))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        self.remove(name, |inode| match inode.kind() {
+            FileKind::Directory => Err(Errno::EISDIR),
+            _ => Ok(()),
+        })
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), Errno> {
+        self.remove(name, |inode| match inode.kind() {
+            FileKind::Directory if inode.is_empty_dir() => Ok(()),
+            FileKind::Directory => Err(Errno::ENOTEMPTY),
+            _ => Err(Errno::ENOTDIR),
+        })
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &dyn Inode, new_name: &str) -> Result<(), Errno> {
+        let new_dir = (new_dir.as_any())
+            .and_then(|any| any.downcast_ref::<TmpInode>())
+            .filter(|dir| Arc::ptr_eq(&dir.fs, &self.fs))
+            .ok_or(Errno::EXDEV)?;
+        check_name(new_name)?;
+
+        if core::ptr::eq(self, new_dir) {
+            let mut dir = self.node.lock();
+            let entries = dir_entries(&mut dir)?;
+            let old_index = find(entries, old_name).ok_or(Errno::ENOENT)?;
+            if old_name == new_name {
+                return Ok(());
+            }
+            if let Some(new_index) = find(entries, new_name) {
+                check_replace(&entries[old_index].1, &entries[new_index].1)?;
+                entries.remove(new_index);
+            }
+            let old_index = find(entries, old_name).ok_or(Errno::ENOENT)?;
+            entries[old_index].0 = String::from(new_name);
+            return Ok(());
+        }
+
+        let new_dir_inode = new_dir;
+        let mut old_dir = self.node.lock();
+        let mut new_dir = new_dir_inode.node.lock();
+        let (old_entries, new_entries) = (dir_entries(&mut old_dir)?, dir_entries(&mut new_dir)?);
+        let old_index = find(old_entries, old_name).ok_or(Errno::ENOENT)?;
+        let src = &old_entries[old_index].1;
+        // Both directories are locked, so neither may be passed to `check_replace`.
+        if core::ptr::eq(&**src, new_dir_inode) {
+            // Into itself
+            return Err(Errno::EINVAL);
+        }
+        if let Some(new_index) = find(new_entries, new_name) {
+            let dst = &new_entries[new_index].1;
+            if core::ptr::eq(&**dst, self) {
+                // The source's parent: not empty
+                return Err(match src.kind() {
+                    FileKind::Directory => Errno::ENOTEMPTY,
+                    _ => Errno::EISDIR,
+                });
+            }
+            check_replace(src, dst)?;
+            new_entries.remove(new_index);
+        }
+        let (_, inode) = old_entries.remove(old_index);
+        new_entries.push((String::from(new_name), inode));
+        Ok(())
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+        let mut dir = self.node.lock();
+        let entries = dir_entries(&mut dir)?;
+        Ok(entries.get(index).map(|(name, inode)| DirEntry {
+            name: name.clone(),
+            kind: inode.kind(),
+        }))
+    }
+}
+
+/// # Errors
+/// ENOTDIR
+fn dir_entries(node: &mut Node) -> Result<&mut Vec<(String, Arc<TmpInode>)>, Errno> {
+    match node {
+        Node::Dir(entries) => Ok(entries),
+        Node::File { .. } => Err(Errno::ENOTDIR),
+    }
+}
+
+fn find(entries: &[(String, Arc<TmpInode>)], name: &str) -> Option<usize> {
+    entries.iter().position(|(entry, _)| entry == name)
+}
+
+/// # Errors
+/// - EEXIST: `.` or `..` (resolved by the vfs, so they always exist)
+/// - ENAMETOOLONG
+fn check_name(name: &str) -> Result<(), Errno> {
+    match name {
+        "" | "." | ".." => Err(Errno::EEXIST),
+        name if name.len() > NAME_MAX => Err(Errno::ENAMETOOLONG),
+        _ => Ok(()),
+    }
+}
+
+/// Can `src` replace `dst` by rename?
+fn check_replace(src: &TmpInode, dst: &TmpInode) -> Result<(), Errno> {
+    match (src.kind(), dst.kind()) {
+        (FileKind::Directory, FileKind::Directory) if !dst.is_empty_dir() => Err(Errno::ENOTEMPTY),
+        (FileKind::Directory, FileKind::Directory) => Ok(()),
+        (FileKind::Directory, _) => Err(Errno::ENOTDIR),
+        (_, FileKind::Directory) => Err(Errno::EISDIR),
+        _ => Ok(()),
+    }
+}
+
+/// The page of the file data at `paddr`
+fn page(paddr: usize) -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(paddr as *mut u8, PAGE_SIZE) }
+}
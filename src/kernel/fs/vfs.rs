@@ -0,0 +1,282 @@
+//! Virtual file system: one interface between the system calls & the file systems.
+//!
+//! A file system(or a device) implements [`Inode`] and is attached to a path by [`mount`].
+//! A path is resolved from the mount with the longest matching path, by [`Inode::lookup`] of each
+//! remaining component. `.` & `..` are resolved by the path string, before the lookup.
+//!
+//! There is no current directory. A relative path is resolved from the root.
+use super::file::OpenFile;
+use crate::info;
+use crate::uaccess::{copy_from_user, copy_to_user};
+use abi::fs::{FileKind, OpenFlags, Stat};
+use abi::Errno;
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+use kernel::spinlock::SpinLock;
+
+/// Max path bytes including NUL
+pub const PATH_MAX: usize = 256;
+/// User buffers are copied via the kernel stack by this size.
+const CHUNK_LEN: usize = 256;
+
+/// One entry of a directory
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: FileKind,
+}
+
+/// A file, directory or device of a file system.
+///
+/// The default methods fail like Linux does for the kinds that don't support them.
+pub trait Inode: Send + Sync {
+    fn stat(&self) -> Stat;
+
+    /// Read from `offset` to `buf`.
+    ///
+    /// # Return
+    /// Read bytes. (0: end of file)
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(Errno::EISDIR)
+    }
+
+    /// Write `buf` at `offset`. The gap after the end of the file is filled with 0.
+    ///
+    /// # Return
+    /// Written bytes
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EISDIR)
+    }
+
+    /// Change the size of the regular file. The extended part is filled with 0.
+    fn truncate(&self, _len: usize) -> Result<(), Errno> {
+        Err(Errno::EISDIR)
+    }
+
+    /// Find the entry `name` of the directory.
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Create the empty regular file `name` in the directory.
+    fn create(&self, _name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Create the empty directory `name` in the directory.
+    fn mkdir(&self, _name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Remove the non directory entry `name` of the directory.
+    fn unlink(&self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Remove the empty directory `name` of the directory.
+    fn rmdir(&self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Move the entry `old_name` of the directory to `new_name` of `new_dir`.
+    /// (see `abi::Syscalls::rename`)
+    ///
+    /// `new_dir` is [`Inode::as_any`] to check that it's the same file system.
+    fn rename(&self, _old_name: &str, _new_dir: &dyn Inode, _new_name: &str) -> Result<(), Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// For the file system to get its own type from `&dyn Inode`. (e.g. the target of rename)
+    fn as_any(&self) -> Option<&dyn Any> {
+        None
+    }
+
+    /// The `index`th entry of the directory. (`None`: no more entries)
+    fn readdir(&self, _index: usize) -> Result<Option<DirEntry>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Device control
+    fn ioctl(&self, _request: usize, _arg: usize) -> Result<usize, Errno> {
+        Err(Errno::ENOTTY)
+    }
+
+    /// [`Inode::read_at`] to the user buffer `[buf, buf + len)`. Read until `len` bytes or the end.
+    ///
+    /// Override to read without the copy via the kernel stack, or to read only once(streams).
+    ///
+    /// # Errors
+    /// EFAULT (The bytes before the fault may have been read.)
+    fn read_user(&self, offset: usize, buf: usize, len: usize) -> Result<usize, Errno> {
+        let mut chunk = [0u8; CHUNK_LEN];
+        let mut read = 0;
+        while read < len {
+            let chunk = &mut chunk[..(len - read).min(CHUNK_LEN)];
+            let n = self.read_at(offset + read, chunk)?;
+            if n == 0 {
+                break;
+            }
+            copy_to_user(buf + read, &chunk[..n])?;
+            read += n;
+        }
+        Ok(read)
+    }
+
+    /// [`Inode::write_at`] the user buffer `[buf, buf + len)`.
+    ///
+    /// Override to write without the copy via the kernel stack, or to write at once.
+    ///
+    /// # Errors
+    /// EFAULT (The bytes before the fault may have been written.)
+    fn write_user(&self, offset: usize, buf: usize, len: usize) -> Result<usize, Errno> {
+        let mut chunk = [0u8; CHUNK_LEN];
+        let mut written = 0;
+        while written < len {
+            let chunk = &mut chunk[..(len - written).min(CHUNK_LEN)];
+            copy_from_user(chunk, buf + written)?;
+            let n = self.write_at(offset + written, chunk)?;
+            if n == 0 {
+                break;
+            }
+            written += n;
+        }
+        Ok(written)
+    }
+}
+
+struct Mount {
+    /// Normalized path components
+    path: Vec<String>,
+    root: Arc<dyn Inode>,
+}
+
+static MOUNTS: SpinLock<Vec<Mount>> = SpinLock::new(Vec::new());
+
+/// Attach the directory `root` at `path`. The previous mount at the same path is replaced.
+///
+/// The mount point doesn't need to exist in the parent file system.
+pub fn mount(path: &str, root: Arc<dyn Inode>) {
+    let path: Vec<String> = components(path).into_iter().map(String::from).collect();
+    info!("vfs: mount /{}", path.join("/"));
+    let mut mounts = MOUNTS.lock();
+    mounts.retain(|mount| mount.path != path);
+    mounts.push(Mount { path, root });
+}
+
+/// Split `path` by `/` & resolve `.`, `..`. (`..` of the root is the root.)
+fn components(path: &str) -> Vec<&str> {
+    let mut components = Vec::new();
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name),
+        }
+    }
+    components
+}
+
+/// Follow `components` from the mount with the longest matching path.
+///
+/// # Errors
+/// - Nothing is mounted on the path: ENOENT
+/// - Errors of [`Inode::lookup`]
+fn walk(components: &[&str]) -> Result<Arc<dyn Inode>, Errno> {
+    let (mut inode, depth) = {
+        let mounts = MOUNTS.lock();
+        let mount = mounts
+            .iter()
+            .filter(|mount| {
+                mount.path.len() <= components.len()
+                    && mount.path.iter().zip(components).all(|(a, b)| a == b)
+            })
+            .max_by_key(|mount| mount.path.len())
+            .ok_or(Errno::ENOENT)?;
+        (mount.root.clone(), mount.path.len())
+    };
+    for name in &components[depth..] {
+        inode = inode.lookup(name)?;
+    }
+    Ok(inode)
+}
+
+/// Find the inode of `path`.
+///
+/// # Errors
+/// - Empty path or no such file: ENOENT
+/// - A non last component is not a directory: ENOTDIR
+pub fn resolve(path: &str) -> Result<Arc<dyn Inode>, Errno> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    walk(&components(path))
+}
+
+/// Find the parent directory of `path`.
+///
+/// # Return
+/// (parent directory, last component)
+///
+/// # Errors
+/// - The root: EEXIST
+/// - Same as [`resolve`]
+pub fn resolve_parent(path: &str) -> Result<(Arc<dyn Inode>, &str), Errno> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    let mut components = components(path);
+    let name = components.pop().ok_or(Errno::EEXIST)?;
+    Ok((walk(&components)?, name))
+}
+
+/// Open `path`. (see `abi::Syscalls::open`)
+pub fn open(path: &str, flags: OpenFlags) -> Result<Arc<OpenFile>, Errno> {
+    let inode = match resolve(path) {
+        Ok(inode) => inode,
+        Err(Errno::ENOENT) if flags.contains(OpenFlags::CREAT) => {
+            let (parent, name) = resolve_parent(path)?;
+            parent.create(name)?
+        }
+        Err(err) => return Err(err),
+    };
+    match inode.stat().kind {
+        FileKind::Directory if flags.writable() => return Err(Errno::EISDIR),
+        FileKind::Regular if flags.writable() && flags.contains(OpenFlags::TRUNC) => {
+            inode.truncate(0)?
+        }
+        _ => {}
+    }
+    Ok(Arc::new(OpenFile::new(inode, flags)))
+}
+
+/// Create the directory `path`. (see `abi::Syscalls::mkdir`)
+pub fn mkdir(path: &str) -> Result<(), Errno> {
+    let (parent, name) = resolve_parent(path)?;
+    parent.mkdir(name).map(|_| ())
+}
+
+/// Remove the file `path`. (see `abi::Syscalls::unlink`)
+pub fn unlink(path: &str) -> Result<(), Errno> {
+    let (parent, name) = resolve_parent(path)?;
+    parent.unlink(name)
+}
+
+/// Remove the directory `path`. (see `abi::Syscalls::rmdir`)
+pub fn rmdir(path: &str) -> Result<(), Errno> {
+    let (parent, name) = resolve_parent(path)?;
+    parent.rmdir(name)
+}
+
+/// Move `old` to `new`. (see `abi::Syscalls::rename`)
+pub fn rename(old: &str, new: &str) -> Result<(), Errno> {
+    let (old_components, new_components) = (components(old), components(new));
+    // A directory can't be moved under itself.
+    if new_components.len() > old_components.len() && new_components.starts_with(&old_components) {
+        return Err(Errno::EINVAL);
+    }
+    let (old_parent, old_name) = resolve_parent(old)?;
+    let (new_parent, new_name) = resolve_parent(new)?;
+    old_parent.rename(old_name, &*new_parent, new_name)
+}
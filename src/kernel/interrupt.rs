@@ -0,0 +1,115 @@
+//! Vectored trap table & interrupt handler registration.
+//!
+//! In `Vectored` mode, the hart jumps to `BASE` for exceptions and to `BASE + 4 * cause` for interrupts.
+//! - ref: https://five-embeddev.com/riscv-isa-manual/latest/supervisor.html#sec:stvec
+use crate::drivers::plic;
+use crate::trap::{
+    external_interrupt_entry, kernel_entry, software_interrupt_entry, timer_interrupt_entry,
+    TrapFrame,
+};
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use kernel::riscv::{scause::Interrupt, sie, sip};
+
+/// Called with the trap frame of the interrupted process.
+pub type InterruptHandler = fn(&mut TrapFrame);
+
+/// Max interrupt cause + 1 (`Interrupt::Unknown` is the sentinel)
+const INTERRUPT_CAUSE_LEN: usize = Interrupt::Unknown as usize;
+/// Registered handler ptr per cause. (0 == not registered)
+static INTERRUPT_HANDLERS: [AtomicUsize; INTERRUPT_CAUSE_LEN] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const UNREGISTERED: AtomicUsize = AtomicUsize::new(0);
+    [UNREGISTERED; INTERRUPT_CAUSE_LEN]
+};
+
+/// Hook `handler` to `cause` & enable that interrupt in `sie`.
+///
+/// A handler registered later replaces the previous one.
+///
+/// # Panics
+/// `cause` is not a supervisor interrupt.
+pub fn register_interrupt_handler(cause: Interrupt, handler: InterruptHandler) {
+    let enable_bit = match cause {
+        Interrupt::SupervisorSoftware => sie::SSIE,
+        Interrupt::SupervisorTimer => sie::STIE,
+        Interrupt::SupervisorExternal => sie::SEIE,
+        _ => panic!("{cause:?} can't be handled in S-Mode"),
+    };
+    INTERRUPT_HANDLERS[cause as usize].store(handler as usize, Ordering::Release);
+    unsafe { sie::set(enable_bit) };
+}
+
+/// Unhook the `cause` handler & disable that interrupt in `sie`.
+pub fn unregister_interrupt_handler(cause: Interrupt) {
+    let enable_bit = match cause {
+        Interrupt::SupervisorSoftware => sie::SSIE,
+        Interrupt::SupervisorTimer => sie::STIE,
+        Interrupt::SupervisorExternal => sie::SEIE,
+        _ => return,
+    };
+    unsafe { sie::clear(enable_bit) };
+    INTERRUPT_HANDLERS[cause as usize].store(0, Ordering::Release);
+}
+
+/// Call the handler registered for `cause`.
+///
+/// # Panics
+/// No handler is registered. (The interrupt would otherwise keep pending forever.)
+pub fn dispatch(cause: Interrupt, f: &mut TrapFrame) {
+    let handler_ptr = INTERRUPT_HANDLERS[cause as usize].load(Ordering::Acquire);
+    assert_ne!(handler_ptr, 0, "unhandled interrupt {cause:?}");
+    let handler: InterruptHandler = unsafe { core::mem::transmute(handler_ptr) };
+    handler(f);
+}
+
+/// Wait for an interrupt & handle it in the current context. (idle loop)
+///
+/// S-Mode runs with `sstatus.SIE = 0`, so the interrupt is not trapped here,
+/// but `wfi` still wakes up when one enabled in `sie` is pending.
+pub fn wait_for_interrupt() {
+    unsafe { asm!("wfi") };
+    if unsafe { sip::read() } & sip::SEIP != 0 {
+        plic::handle_irqs();
+    }
+}
+
+/// Vector table to write to stvec with `TrapMode::Vectored`.
+///
+/// | index | cause                          | entry                      |
+/// |-------|--------------------------------|----------------------------|
+/// |   0   | exception(& user software)     | `kernel_entry`             |
+/// |   1   | supervisor software interrupt  | `software_interrupt_entry` |
+/// |   5   | supervisor timer interrupt     | `timer_interrupt_entry`    |
+/// |   9   | supervisor external interrupt  | `external_interrupt_entry` |
+///
+/// Reserved & user-level causes fall back to `kernel_entry`(=> unexpected trap).
+#[naked]
+#[repr(align(4))] // Set the least significant 2 bits to 0 for mode flag.
+pub extern "C" fn trap_vector() {
+    unsafe {
+        asm!(
+            ".option push",
+            // Each entry must be exactly 4 bytes, so don't let `j` be compressed to `c.j`.
+            ".option norvc",
+            "j {exception}", // 0
+            "j {software}",  // 1
+            "j {exception}", // 2
+            "j {exception}", // 3
+            "j {exception}", // 4
+            "j {timer}",     // 5
+            "j {exception}", // 6
+            "j {exception}", // 7
+            "j {exception}", // 8
+            "j {external}",  // 9
+            ".option pop",
+            exception = sym kernel_entry,
+            software = sym software_interrupt_entry,
+            timer = sym timer_interrupt_entry,
+            external = sym external_interrupt_entry,
+            options(noreturn)
+        );
+    }
+}
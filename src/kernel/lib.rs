@@ -3,5 +3,6 @@
 #![feature(asm_const)]
 #![feature(fn_align)]
 pub mod addr;
+pub mod ring_buffer;
 pub mod riscv;
-pub mod syscall_num;
+pub mod spinlock;
@@ -0,0 +1,268 @@
+//! Linux rv32 personality: run statically linked riscv32 Linux binaries(Rust/musl).
+//!
+//! - syscall number: a7
+//! - arguments: a0..=a5
+//! - return value: a0 (`-(errno)` on failure)
+//!
+//! Only the minimum needed by a static binary's startup code, stdio & allocator is implemented.
+//! Others return `ENOSYS`.
+//! - ref: https://github.com/torvalds/linux/blob/master/include/uapi/asm-generic/unistd.h
+use crate::elf::LoadedElf;
+use crate::fs::{self, current_file, PATH_MAX};
+use crate::pages::{map_user_pages, MapError, PAGE_R, PAGE_SIZE, PAGE_W};
+use crate::proc::{exit_current_proc, with_current_proc};
+use crate::strace::{self, FmtArgs};
+use crate::trap::TrapFrame;
+use crate::uaccess::{copy_str_from_user, read_user, write_user};
+use abi::fs::OpenFlags;
+use abi::Errno;
+use kernel::addr::align_down;
+use kernel::riscv::time;
+
+/// Initial user stack end(exclusive)
+pub const STACK_TOP: usize = 0x7000_0000;
+const STACK_PAGES: usize = 16;
+/// The anonymous mmap region grows down from here. (1 page guard below the stack)
+pub const MMAP_TOP: usize = STACK_TOP - (STACK_PAGES + 1) * PAGE_SIZE;
+/// Max heap size by `brk`
+pub const BRK_MAX: usize = 16 * 1024 * 1024;
+/// ELF segments must end below this.
+pub const USER_END: usize = 0x6000_0000;
+
+type LinuxSyscallFn = fn(&[usize; 6]) -> Result<usize, Errno>;
+
+const SYS_IOCTL: usize = 29;
+const SYS_OPENAT: usize = 56;
+const SYS_CLOSE: usize = 57;
+const SYS_READ: usize = 63;
+const SYS_WRITE: usize = 64;
+const SYS_WRITEV: usize = 66;
+const SYS_EXIT: usize = 93;
+const SYS_EXIT_GROUP: usize = 94;
+const SYS_SET_TID_ADDRESS: usize = 96;
+const SYS_GETPID: usize = 172;
+const SYS_BRK: usize = 214;
+const SYS_MUNMAP: usize = 215;
+/// rv32 has `mmap2`(offset in pages) only.
+const SYS_MMAP2: usize = 222;
+/// rv32 has the 64bit time version only.
+const SYS_CLOCK_GETTIME64: usize = 403;
+
+/// (syscall number, name, implementation)
+const LINUX_SYSCALLS: &[(usize, &str, LinuxSyscallFn)] = &[
+    (SYS_IOCTL, "ioctl", sys_ioctl),
+    (SYS_OPENAT, "openat", sys_openat),
+    (SYS_CLOSE, "close", sys_close),
+    (SYS_READ, "read", sys_read),
+    (SYS_WRITE, "write", sys_write),
+    (SYS_WRITEV, "writev", sys_writev),
+    (SYS_EXIT, "exit", sys_exit),
+    (SYS_EXIT_GROUP, "exit_group", sys_exit),
+    (SYS_SET_TID_ADDRESS, "set_tid_address", sys_getpid),
+    (SYS_GETPID, "getpid", sys_getpid),
+    (SYS_BRK, "brk", sys_brk),
+    (SYS_MUNMAP, "munmap", sys_munmap),
+    (SYS_MMAP2, "mmap2", sys_mmap2),
+    (SYS_CLOCK_GETTIME64, "clock_gettime64", sys_clock_gettime64),
+];
+
+/// # Parameters
+/// - trace_pid: `Some(pid)` if the process is traced (see [`crate::strace`])
+pub fn handle_syscall(f: &mut TrapFrame, trace_pid: Option<usize>) {
+    let start_ticks = time::read();
+    let args = [f.a0, f.a1, f.a2, f.a3, f.a4, f.a5];
+    let syscall = LINUX_SYSCALLS.iter().find(|(sysno, _, _)| *sysno == f.a7);
+    let result = match syscall {
+        Some((_, _, handler)) => handler(&args),
+        None => Err(Errno::ENOSYS),
+    };
+    if let Some(pid) = trace_pid {
+        // No typed decoding for Linux syscalls, so show the raw registers.
+        strace::log_syscall(
+            pid,
+            syscall.map_or("unknown", |(_, name, _)| name),
+            FmtArgs(|f| write!(f, "{:#x?}", args)),
+            &result,
+            start_ticks,
+        );
+    }
+    f.a0 = match result {
+        Ok(ret) => ret,
+        Err(errno) => -(errno as isize) as usize,
+    };
+}
+
+// Auxiliary vector types
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+const AT_UID: usize = 11;
+const AT_EUID: usize = 12;
+const AT_GID: usize = 13;
+const AT_EGID: usize = 14;
+const AT_RANDOM: usize = 25;
+
+/// Map the user stack & build the initial process stack.
+///
+/// ```txt
+/// STACK_TOP  -> | AT_RANDOM bytes(16) |
+///               | argv[0] string      |
+///               | (align 16)          |
+///               | auxv pairs, AT_NULL |
+///               | envp: NULL          |
+///               | argv[0], NULL       |
+/// sp         -> | argc                |
+/// ```
+///
+/// # Return
+/// Initial user sp
+pub fn init_stack(root_ppn: usize, elf: &LoadedElf) -> Result<usize, MapError> {
+    let top_page_vaddr = STACK_TOP - PAGE_SIZE;
+    let mut top_page_paddr = 0;
+    map_user_pages(
+        root_ppn,
+        STACK_TOP - STACK_PAGES * PAGE_SIZE,
+        STACK_TOP,
+        PAGE_R | PAGE_W,
+        |vaddr, paddr| {
+            if vaddr == top_page_vaddr {
+                top_page_paddr = paddr;
+            }
+        },
+    )?;
+    // Everything fits in the top page, so write it through the physical address.
+    let top_page = unsafe { core::slice::from_raw_parts_mut(top_page_paddr as *mut u8, PAGE_SIZE) };
+    let mut sp = STACK_TOP;
+    let mut push_bytes = |bytes: &[u8]| {
+        sp -= bytes.len();
+        top_page[sp - top_page_vaddr..][..bytes.len()].copy_from_slice(bytes);
+        sp
+    };
+
+    let mut random = [0u8; 16];
+    let mut seed = time::read() | 1;
+    for byte in random.iter_mut() {
+        // xorshift64
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        *byte = seed as u8;
+    }
+    let random_ptr = push_bytes(&random);
+    let argv0_ptr = push_bytes(b"app\0");
+
+    #[rustfmt::skip]
+    let words = [
+        1, // argc
+        argv0_ptr, 0, // argv
+        0, // envp
+        AT_PHDR, elf.phdr,
+        AT_PHENT, elf.phent,
+        AT_PHNUM, elf.phnum,
+        AT_PAGESZ, PAGE_SIZE,
+        AT_ENTRY, elf.entry,
+        AT_UID, 0,
+        AT_EUID, 0,
+        AT_GID, 0,
+        AT_EGID, 0,
+        AT_RANDOM, random_ptr,
+        AT_NULL, 0,
+    ];
+    let sp = align_down(sp - core::mem::size_of_val(&words), 16);
+    for (i, word) in words.iter().enumerate() {
+        let offset = sp - top_page_vaddr + i * core::mem::size_of::<usize>();
+        top_page[offset..][..core::mem::size_of::<usize>()].copy_from_slice(&word.to_le_bytes());
+    }
+    Ok(sp)
+}
+
+/// ioctl(fd, request, arg): No terminal control.
+fn sys_ioctl(_args: &[usize; 6]) -> Result<usize, Errno> {
+    Err(Errno::ENOTTY)
+}
+
+/// openat(dirfd, path, flags, mode): There is no current directory, so `dirfd` is ignored.
+/// The flags are the same values as [`OpenFlags`].
+fn sys_openat(args: &[usize; 6]) -> Result<usize, Errno> {
+    let [_dirfd, path, flags, ..] = *args;
+    let mut path_buf = [0u8; PATH_MAX];
+    let path = copy_str_from_user(&mut path_buf, path)?;
+    let file = fs::open(path, OpenFlags::from_bits(flags as u32))?;
+    with_current_proc(|proc| proc.fds().insert(file))
+}
+
+/// close(fd)
+fn sys_close(args: &[usize; 6]) -> Result<usize, Errno> {
+    with_current_proc(|proc| proc.fds().remove(args[0])).map(|_| 0)
+}
+
+/// read(fd, buf, len): The console blocks until input.
+fn sys_read(args: &[usize; 6]) -> Result<usize, Errno> {
+    let [fd, buf, len, ..] = *args;
+    current_file(fd)?.read(buf, len)
+}
+
+/// write(fd, buf, len)
+fn sys_write(args: &[usize; 6]) -> Result<usize, Errno> {
+    let [fd, buf, len, ..] = *args;
+    current_file(fd)?.write(buf, len)
+}
+
+/// writev(fd, iov, iovcnt)
+fn sys_writev(args: &[usize; 6]) -> Result<usize, Errno> {
+    let [fd, iov, iovcnt, ..] = *args;
+    let file = current_file(fd)?;
+    let mut written = 0;
+    for i in 0..iovcnt {
+        // struct iovec { void *iov_base; size_t iov_len; }
+        let [base, len] = read_user::<[usize; 2]>(iov + i * core::mem::size_of::<[usize; 2]>())?;
+        written += file.write(base, len)?;
+    }
+    Ok(written)
+}
+
+/// exit(status), exit_group(status)
+fn sys_exit(args: &[usize; 6]) -> Result<usize, Errno> {
+    exit_current_proc(args[0] as i32);
+    Ok(0)
+}
+
+/// getpid(), set_tid_address(tidptr): single thread, so tid == pid.
+fn sys_getpid(_args: &[usize; 6]) -> Result<usize, Errno> {
+    Ok(with_current_proc(|proc| proc.pid()))
+}
+
+/// brk(addr): `addr == 0` returns the current break.
+fn sys_brk(args: &[usize; 6]) -> Result<usize, Errno> {
+    Ok(with_current_proc(|proc| proc.set_brk(args[0])))
+}
+
+/// munmap(addr, len): Pages are never freed yet.
+fn sys_munmap(_args: &[usize; 6]) -> Result<usize, Errno> {
+    Ok(0)
+}
+
+/// mmap2(addr, len, prot, flags, fd, pgoff): Private anonymous mapping only.
+fn sys_mmap2(args: &[usize; 6]) -> Result<usize, Errno> {
+    const MAP_FIXED: usize = 0x10;
+    const MAP_ANONYMOUS: usize = 0x20;
+    let [_addr, len, _prot, flags, ..] = *args;
+    if flags & MAP_ANONYMOUS == 0 || flags & MAP_FIXED != 0 || len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    with_current_proc(|proc| proc.mmap_anonymous(len)).ok_or(Errno::ENOMEM)
+}
+
+/// clock_gettime64(clockid, tp): All clocks are the `time` CSR.
+fn sys_clock_gettime64(args: &[usize; 6]) -> Result<usize, Errno> {
+    let tp = args[1];
+    let ticks = time::read();
+    let sec = ticks / time::TIMEBASE_FREQ;
+    let nsec = (ticks % time::TIMEBASE_FREQ) * (1_000_000_000 / time::TIMEBASE_FREQ);
+    // struct __kernel_timespec { i64 tv_sec; i64 tv_nsec; }
+    write_user(tp, [sec, nsec])?;
+    Ok(0)
+}
@@ -0,0 +1,227 @@
+//! Leveled kernel log.
+//!
+//! Each message is stamped with the `time` CSR(as seconds) and the current pid, kept in the dmesg
+//! ring buffer(read by the `dmesg` syscall) and mirrored to the console by default.
+//! ```txt
+//! [    0.012345] [pid 1] INFO  os::proc: process 1 exit (status 0)
+//! ```
+//!
+//! The filter is given at build time by `KLOG`(like `RUST_LOG`): `<level>` or `<module path>=<level>`
+//! separated by `,`. The longest matching module path wins. (default: `info`)
+//! - e.g. `KLOG=warn,os::proc=trace cargo run`
+use crate::console;
+use crate::proc::current_pid;
+use core::cell::UnsafeCell;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+use kernel::ring_buffer::RingBuffer;
+use kernel::riscv::time;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "error" => Self::Error,
+            "warn" => Self::Warn,
+            "info" => Self::Info,
+            "debug" => Self::Debug,
+            "trace" => Self::Trace,
+            _ => return None,
+        })
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN ",
+            Self::Info => "INFO ",
+            Self::Debug => "DEBUG",
+            Self::Trace => "TRACE",
+        }
+    }
+}
+
+/// Filter spec (see module doc)
+const FILTER: &str = match option_env!("KLOG") {
+    Some(filter) => filter,
+    None => "info",
+};
+
+/// Is `level` of `module` logged by [`FILTER`]?
+pub fn enabled(level: Level, module: &str) -> bool {
+    // (matched module path length, level)
+    let mut max_level = (0, Level::Info);
+    for directive in FILTER.split(',').map(str::trim) {
+        let (path, level) = match directive.split_once('=') {
+            Some((path, level)) => (path, level),
+            None => ("", directive),
+        };
+        let Some(level) = Level::from_name(level) else {
+            continue;
+        };
+        let is_match = path.is_empty()
+            || module == path
+            || module
+                .strip_prefix(path)
+                .is_some_and(|rest| rest.starts_with("::"));
+        if is_match && path.len() >= max_level.0 {
+            max_level = (path.len(), level);
+        }
+    }
+    level <= max_level.1
+}
+
+const DMESG_LEN: usize = 16 * 1024;
+
+struct Dmesg(UnsafeCell<RingBuffer<u8, DMESG_LEN>>);
+
+/// Only accessed in S-Mode, where interrupts are disabled. (single hart)
+unsafe impl Sync for Dmesg {}
+
+impl Dmesg {
+    fn with<R>(&self, f: impl FnOnce(&mut RingBuffer<u8, DMESG_LEN>) -> R) -> R {
+        f(unsafe { &mut *self.0.get() })
+    }
+}
+
+/// Log text. (The oldest bytes are dropped when full.)
+static DMESG: Dmesg = Dmesg(UnsafeCell::new(RingBuffer::new(0)));
+static MIRROR_TO_CONSOLE: AtomicBool = AtomicBool::new(true);
+
+/// Print the log messages to the console too? (default: true)
+pub fn set_console_mirror(enable: bool) {
+    MIRROR_TO_CONSOLE.store(enable, Ordering::Release);
+}
+
+/// Bytes of the console output formatted before taking the console lock
+const LINE_BUF_LEN: usize = 256;
+
+/// Writes to dmesg & (optionally) the console.
+///
+/// The console output is staged on the stack, so the console is not locked while formatting.
+/// (Long messages are flushed per [`LINE_BUF_LEN`] bytes.)
+struct LogWriter {
+    mirror: bool,
+    buf: [u8; LINE_BUF_LEN],
+    len: usize,
+}
+
+impl LogWriter {
+    fn flush(&mut self) {
+        if self.len > 0 {
+            console::lock().write_bytes(&self.buf[..self.len]);
+            self.len = 0;
+        }
+    }
+}
+
+impl fmt::Write for LogWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        DMESG.with(|dmesg| s.bytes().for_each(|byte| dmesg.push_overwrite(byte)));
+        if !self.mirror {
+            return Ok(());
+        }
+        let mut bytes = s.as_bytes();
+        while !bytes.is_empty() {
+            if self.len == LINE_BUF_LEN {
+                self.flush();
+            }
+            let n = bytes.len().min(LINE_BUF_LEN - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
+        }
+        Ok(())
+    }
+}
+
+/// Use the macros(`info!`, ...) instead.
+pub fn log(level: Level, module: &str, args: fmt::Arguments) {
+    if !enabled(level, module) {
+        return;
+    }
+    let ticks = time::read();
+    let secs = ticks / time::TIMEBASE_FREQ;
+    let micros = (ticks % time::TIMEBASE_FREQ) * 1_000_000 / time::TIMEBASE_FREQ;
+    let pid = current_pid();
+    let mut writer = LogWriter {
+        mirror: MIRROR_TO_CONSOLE.load(Ordering::Acquire),
+        buf: [0; LINE_BUF_LEN],
+        len: 0,
+    };
+    let _ = write!(writer, "[{:>5}.{:06}] ", secs, micros);
+    let _ = match pid {
+        Some(pid) => write!(writer, "[pid {}] ", pid),
+        None => write!(writer, "[pid -] "),
+    };
+    let _ = writeln!(writer, "{} {}: {}", level.label(), module, args);
+    writer.flush();
+}
+
+/// Bytes in dmesg
+pub fn dmesg_len() -> usize {
+    DMESG.with(|dmesg| dmesg.len())
+}
+
+/// Copy dmesg from the byte `offset`(0: the oldest byte) to `buf`.
+///
+/// # Return
+/// Copied bytes
+pub fn read_dmesg(offset: usize, buf: &mut [u8]) -> usize {
+    DMESG.with(|dmesg| {
+        let (older, newer) = dmesg.as_slices();
+        let mut copied = 0;
+        let mut offset = offset;
+        for part in [older, newer] {
+            let Some(src) = part.get(offset..) else {
+                offset -= part.len();
+                continue;
+            };
+            let n = src.len().min(buf.len() - copied);
+            buf[copied..copied + n].copy_from_slice(&src[..n]);
+            copied += n;
+            offset = 0;
+        }
+        copied
+    })
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::log::log($level, module_path!(), format_args!($fmt $(, $($arg)+)?))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg: tt)+) => { $crate::log!($crate::log::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg: tt)+) => { $crate::log!($crate::log::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg: tt)+) => { $crate::log!($crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg: tt)+) => { $crate::log!($crate::log::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg: tt)+) => { $crate::log!($crate::log::Level::Trace, $($arg)+) };
+}
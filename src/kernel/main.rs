@@ -7,17 +7,38 @@
 #![feature(naked_functions)]
 #![feature(panic_info_message)]
 pub mod allocator;
+pub mod backtrace;
+pub mod block;
 pub mod console;
+pub mod drivers;
+pub mod elf;
+pub mod fs;
+pub mod interrupt;
+pub mod linux;
+pub mod log;
 pub mod pages;
+pub mod power;
 pub mod proc;
 pub mod sbi;
+pub mod strace;
+pub mod syscall;
 pub mod trap;
+pub mod tty;
+pub mod uaccess;
 
 extern crate alloc;
-use crate::{proc::Executer, trap::kernel_entry};
+use crate::{
+    drivers::test_finisher,
+    interrupt::trap_vector,
+    proc::{Executer, SpawnOptions},
+    sbi::srst::ResetReason,
+};
 use core::{arch::asm, panic::PanicInfo};
 use kernel::riscv::stvec;
 
+/// QEMU exit status on kernel panic
+const PANIC_EXIT_CODE: u16 = 101;
+
 // Defined symbols by kernel.ld
 extern "C" {
     fn __bss();
@@ -39,17 +60,29 @@ fn clear_bss() {
 
 fn kernel_main() {
     clear_bss();
-    unsafe { stvec::write(kernel_entry as usize, stvec::TrapMode::Direct) };
+    console::init();
+    sbi::init();
+    unsafe { stvec::write(trap_vector as usize, stvec::TrapMode::Vectored) };
+    syscall::init();
+    drivers::plic::init();
+    console::init_input();
+    drivers::virtio_blk::init();
+    fs::init();
 
     let mut proc_runner = Executer::new();
+    // Build with `STRACE=1` to trace all processes from the start.
+    let options = SpawnOptions {
+        trace: option_env!("STRACE").is_some(),
+    };
     let apps_list = pages::get_user_app_list();
     for app_n in apps_list {
         if app_n.0 != 0 {
-            proc_runner.push(app_n);
+            proc_runner.push(app_n, options);
         }
     }
     proc_runner.run();
-    panic!("task all complete!");
+    info!("all processes exited");
+    power::power_off(ResetReason::NoReason);
 }
 
 #[link_section = ".text.boot"]
@@ -59,6 +92,7 @@ pub extern "C" fn boot() {
     unsafe {
         asm!(
             "la sp, {stack_top}", // Move the stack pointer to the __stack_top address
+            "mv fp, zero",        // Terminate the frame pointer chain for backtraces
             "call {kernel_main}",
             stack_top = sym __stack_top,
             kernel_main = sym kernel_main,
@@ -69,6 +103,8 @@ pub extern "C" fn boot() {
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // The panicked code may hold the console.
+    unsafe { console::force_unlock() };
     match info.location() {
         Some(location) => {
             println!(
@@ -80,5 +116,6 @@ fn panic(info: &PanicInfo) -> ! {
         }
         None => println!("[kernel] Panicked: {}", info.message().unwrap()),
     };
-    loop {}
+    backtrace::print_backtrace();
+    test_finisher::exit_qemu(PANIC_EXIT_CODE)
 }
@@ -1,5 +1,10 @@
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use kernel::addr::{is_aligned, PhysAddr, PhysPageNum, VirtAddr};
+use kernel::addr::{align_down, is_aligned, PhysAddr, PhysPageNum, VirtAddr};
+use kernel::riscv::sfence_vma_all;
+use kernel::spinlock::SpinLock;
+
+use crate::drivers::MMIO_REGIONS;
+use crate::println;
 
 extern "C" {
     /// defined by kernel.ld
@@ -11,7 +16,15 @@ extern "C" {
 }
 
 /// n time allocate pages & 0 fill, return it's addr
+///
+/// # Panics
+/// out of memory
 pub fn alloc_pages(n: usize) -> PhysAddr {
+    try_alloc_pages(n).expect("out of memory")
+}
+
+/// [`alloc_pages`] that returns `None` when out of memory.
+pub fn try_alloc_pages(n: usize) -> Option<PhysAddr> {
     static NEXT_PADDR: AtomicUsize = AtomicUsize::new(0);
     fn next_paddr_init_once() {
         // NOTE: global flag variable
@@ -25,20 +38,50 @@ pub fn alloc_pages(n: usize) -> PhysAddr {
 
     next_paddr_init_once();
     let paddr = NEXT_PADDR.load(Ordering::Acquire);
+    if paddr + n * PAGE_SIZE >= __free_ram_end as usize {
+        return None;
+    }
     NEXT_PADDR.fetch_add(n * PAGE_SIZE, Ordering::Relaxed);
 
-    assert!(
-        NEXT_PADDR.load(Ordering::Acquire) < __free_ram_end as usize,
-        "out of memory"
-    );
-
     unsafe {
         let paddr = core::slice::from_raw_parts_mut(paddr as *mut u8, n * PAGE_SIZE);
         paddr.fill(0);
-        (paddr.as_ptr() as usize).into()
+        Some((paddr.as_ptr() as usize).into())
     }
 }
 
+/// The last freed page. The first word of a free page is the previous one. (0: none)
+static FREE_PAGES: SpinLock<usize> = SpinLock::new(0);
+
+/// Allocate one 0 filled page. The pages freed by [`free_page`] are reused first.
+///
+/// # Return
+/// `None` if out of memory.
+pub fn alloc_page() -> Option<PhysAddr> {
+    let mut free_pages = FREE_PAGES.lock();
+    if *free_pages == 0 {
+        drop(free_pages);
+        return try_alloc_pages(1);
+    }
+    let paddr = *free_pages;
+    unsafe {
+        *free_pages = (paddr as *const usize).read();
+        core::slice::from_raw_parts_mut(paddr as *mut u8, PAGE_SIZE).fill(0);
+    }
+    Some(paddr.into())
+}
+
+/// Give back the page of [`alloc_page`] for reuse.
+///
+/// # Safety
+/// `paddr` is allocated by [`alloc_page`], and never used after this.
+pub unsafe fn free_page(paddr: PhysAddr) {
+    let paddr: usize = paddr.into();
+    let mut free_pages = FREE_PAGES.lock();
+    (paddr as *mut usize).write(*free_pages);
+    *free_pages = paddr;
+}
+
 pub const PAGE_SIZE: usize = 0x1000;
 /// The number of page table entries in SV32 consists of 2^10, each of which is specified as 4 bytes.
 /// - ref: https://five-embeddev.com/riscv-isa-manual/latest/supervisor.html#sec:sv32
@@ -46,16 +89,17 @@ const PAGE_TABLE_LEN: usize = 1024;
 /// Virtual page enable flag in satp(Supervisor address and protection) register
 pub const SATP_SV32: usize = 1 << 31;
 /// valid flag bit
-const PAGE_V: usize = 1 << 0;
+pub const PAGE_V: usize = 1 << 0;
 /// can read flag bit
-const PAGE_R: usize = 1 << 1;
+pub const PAGE_R: usize = 1 << 1;
 /// can write flag bit
-const PAGE_W: usize = 1 << 2;
+pub const PAGE_W: usize = 1 << 2;
 /// executable flag bit
-const PAGE_X: usize = 1 << 3;
-#[allow(unused)]
+pub const PAGE_X: usize = 1 << 3;
 /// can access on user mode flag bit
-const PAGE_U: usize = 1 << 4;
+pub const PAGE_U: usize = 1 << 4;
+/// All permission flag bits of a leaf entry
+const PAGE_FLAGS_MASK: usize = (1 << 10) - 1;
 
 /// 4byte
 ///
@@ -70,6 +114,98 @@ impl PageTableEntry {
     fn is_valid(&self) -> bool {
         (self.0 & PAGE_V) != 0
     }
+
+    fn flags(&self) -> usize {
+        self.0 & PAGE_FLAGS_MASK
+    }
+
+    /// Physical address of the next level table or the mapped page
+    fn paddr(&self) -> usize {
+        (self.0 >> 10) * PAGE_SIZE
+    }
+}
+
+/// Find the leaf entry of `vaddr`.
+fn walk(root_ppn: PhysAddr, vaddr: VirtAddr) -> Option<&'static mut PageTableEntry> {
+    let page_table_ptr: usize = root_ppn.into();
+    let vaddr: usize = vaddr.into();
+    let table1 = unsafe {
+        core::slice::from_raw_parts_mut(page_table_ptr as *mut PageTableEntry, PAGE_TABLE_LEN)
+    };
+    let vpn1 = (vaddr >> 22) & (PAGE_TABLE_LEN - 1);
+    if !table1[vpn1].is_valid() {
+        return None;
+    }
+
+    let table0 = unsafe {
+        core::slice::from_raw_parts_mut(table1[vpn1].paddr() as *mut PageTableEntry, PAGE_TABLE_LEN)
+    };
+    let vpn0 = (vaddr >> 12) & (PAGE_TABLE_LEN - 1);
+    match table0[vpn0].is_valid() {
+        true => Some(&mut table0[vpn0]),
+        false => None,
+    }
+}
+
+/// Page table entry flags as `DAGUXWRV`(`-` if not set).
+struct PteFlags(usize);
+
+impl core::fmt::Display for PteFlags {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (bit, name) in ['V', 'R', 'W', 'X', 'U', 'G', 'A', 'D']
+            .into_iter()
+            .enumerate()
+            .rev()
+        {
+            let ch = match self.0 & (1 << bit) != 0 {
+                true => name,
+                false => '-',
+            };
+            write!(f, "{}", ch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Print each level of the page table walk for `vaddr`. (for the fatal trap dump)
+///
+/// # Parameters
+/// - root_ppn: Physical address of the root page table
+pub fn dump_walk(root_ppn: usize, vaddr: usize) {
+    println!("page walk: vaddr={:#010x} root={:#010x}", vaddr, root_ppn);
+    let mut table_ptr = root_ppn;
+    for (level, shift) in [(1, 22), (0, 12)] {
+        let index = (vaddr >> shift) & (PAGE_TABLE_LEN - 1);
+        let pte = unsafe { &*(table_ptr as *const PageTableEntry).add(index) };
+        println!(
+            "  L{}[{:>4}]: pte={:#010x} paddr={:#010x} flags={}",
+            level,
+            index,
+            pte.0,
+            pte.paddr(),
+            PteFlags(pte.flags())
+        );
+        if !pte.is_valid() {
+            println!("  -> not mapped");
+            return;
+        }
+        if pte.flags() & (PAGE_R | PAGE_W | PAGE_X) != 0 {
+            // leaf (L1 leaf is a 4MiB megapage)
+            println!("  -> paddr={:#010x}", pte.paddr() + vaddr % (1 << shift));
+            return;
+        }
+        table_ptr = pte.paddr();
+    }
+    println!("  -> no leaf entry");
+}
+
+/// Virtual address -> (Physical address, page flags)
+///
+/// # Return
+/// `None` if `vaddr` is not mapped.
+pub fn translate(root_ppn: usize, vaddr: usize) -> Option<(usize, usize)> {
+    let pte = walk(root_ppn.into(), vaddr.into())?;
+    Some((pte.paddr() + vaddr % PAGE_SIZE, pte.flags()))
 }
 
 /// # Parameters
@@ -80,7 +216,7 @@ impl PageTableEntry {
 ///
 /// # Panics
 /// - vaddr & paddr must be aligned to PAGE_SIZE(default: 4096)
-fn map_page(root_ppn: PhysAddr, vaddr: VirtAddr, paddr: PhysAddr, flags: usize) {
+pub fn map_page(root_ppn: PhysAddr, vaddr: VirtAddr, paddr: PhysAddr, flags: usize) {
     let page_table_ptr: usize = root_ppn.into();
     let vaddr: usize = vaddr.into();
     assert!(is_aligned(vaddr, PAGE_SIZE), "unaligned vaddr {vaddr:x}");
@@ -117,6 +253,15 @@ pub fn ident_map_in_kernel(root_ppn: usize) {
         );
         paddr += PAGE_SIZE
     }
+
+    // Device registers (S-Mode only)
+    for (base, size) in MMIO_REGIONS {
+        let mut paddr = align_down(*base, PAGE_SIZE);
+        while paddr < base + size {
+            map_page(root_ppn.into(), paddr.into(), paddr.into(), PAGE_R | PAGE_W);
+            paddr += PAGE_SIZE
+        }
+    }
 }
 
 const MAX_APP_NUM: usize = 16;
@@ -182,3 +327,76 @@ pub fn map_one_app(root_ppn: usize, app_start_ptr: usize, app_end_ptr: usize) {
         offset += PAGE_SIZE
     }
 }
+
+/// Why [`map_user_pages`] failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapError {
+    /// The address of a page already mapped for the kernel only (e.g. the MMIO registers)
+    KernelPage(usize),
+    OutOfMemory,
+}
+
+/// Map 0 filled pages to the unmapped pages of `[vaddr_start, vaddr_end)`.
+/// Already mapped user pages are kept, and `flags` are added to them.
+///
+/// # Return
+/// Physical address of each page is passed to `on_page(vaddr, paddr)`.
+///
+/// # Errors
+/// Nothing is mapped (or changed) then.
+pub fn map_user_pages(
+    root_ppn: usize,
+    vaddr_start: usize,
+    vaddr_end: usize,
+    flags: usize,
+    mut on_page: impl FnMut(usize, usize),
+) -> Result<(), MapError> {
+    let vaddr_start = align_down(vaddr_start, PAGE_SIZE);
+    let mut vaddr = vaddr_start;
+    while vaddr < vaddr_end {
+        match walk(root_ppn.into(), vaddr.into()) {
+            Some(pte) if pte.flags() & PAGE_U == 0 => return Err(MapError::KernelPage(vaddr)),
+            _ => vaddr += PAGE_SIZE,
+        }
+    }
+
+    // Allocate all the unmapped pages first, so that running out of memory can be undone.
+    let mut vaddr = vaddr_start;
+    while vaddr < vaddr_end {
+        if walk(root_ppn.into(), vaddr.into()).is_none() {
+            let Some(page) = alloc_page() else {
+                unmap_new_user_pages(root_ppn, vaddr_start, vaddr);
+                return Err(MapError::OutOfMemory);
+            };
+            // Without `PAGE_U` until all are mapped: it marks the pages to undo.
+            map_page(root_ppn.into(), vaddr.into(), page, flags);
+        }
+        vaddr += PAGE_SIZE;
+    }
+
+    let mut vaddr = vaddr_start;
+    while vaddr < vaddr_end {
+        let pte = walk(root_ppn.into(), vaddr.into()).unwrap();
+        pte.0 |= flags | PAGE_U;
+        on_page(vaddr, pte.paddr());
+        vaddr += PAGE_SIZE;
+    }
+    sfence_vma_all();
+    Ok(())
+}
+
+/// Unmap & free the pages [`map_user_pages`] mapped(without `PAGE_U`) in `[vaddr_start, vaddr_end)`.
+fn unmap_new_user_pages(root_ppn: usize, vaddr_start: usize, vaddr_end: usize) {
+    let mut vaddr = vaddr_start;
+    while vaddr < vaddr_end {
+        if let Some(pte) = walk(root_ppn.into(), vaddr.into()) {
+            if pte.flags() & PAGE_U == 0 {
+                let paddr = pte.paddr();
+                pte.0 = 0;
+                unsafe { free_page(paddr.into()) };
+            }
+        }
+        vaddr += PAGE_SIZE;
+    }
+    sfence_vma_all();
+}
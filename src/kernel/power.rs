@@ -0,0 +1,35 @@
+//! Power off & reboot via the SBI system reset(SRST) extension.
+//!
+//! The reset reason does not reach the QEMU exit status. (OpenSBI ignores it)
+//! Use `drivers::test_finisher::exit_qemu` to exit QEMU with a status code.
+use crate::println;
+use crate::sbi::{
+    srst::{self, ResetReason, ResetType},
+    SbiError,
+};
+use core::arch::asm;
+
+/// Power off the machine.
+///
+/// # Return
+/// Only on failure.
+pub fn shutdown(reason: ResetReason) -> SbiError {
+    srst::system_reset(ResetType::Shutdown, reason)
+}
+
+/// Reboot the machine.
+///
+/// # Return
+/// Only on failure. (e.g. `NotSupported` without the SRST extension)
+pub fn reboot() -> SbiError {
+    srst::system_reset(ResetType::ColdReboot, ResetReason::NoReason)
+}
+
+/// Power off. If it fails, stop the CPU here.
+pub fn power_off(reason: ResetReason) -> ! {
+    let err = shutdown(reason);
+    println!("[kernel] failed to power off: {}", err);
+    loop {
+        unsafe { asm!("wfi") };
+    }
+}
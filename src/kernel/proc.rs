@@ -4,9 +4,18 @@ use core::{
 };
 
 use crate::{
-    pages::{alloc_pages, ident_map_in_kernel, map_one_app, PAGE_SIZE, SATP_SV32},
-    println,
+    drivers::overlaps_mmio,
+    elf::{self, Elf, ELFOSABI_LINUX, ELFOSABI_NONE},
+    error,
+    fs::FdTable,
+    info, interrupt, linux,
+    pages::{
+        alloc_pages, ident_map_in_kernel, map_one_app, map_user_pages, PAGE_R, PAGE_SIZE, PAGE_W,
+        SATP_SV32, USER_BASE,
+    },
+    println, strace,
 };
+use kernel::addr::align_up;
 
 // const PROCS_MAX: usize = 8;
 const PROCS_MAX: usize = 3;
@@ -27,14 +36,101 @@ fn check_init_runner() {
 /// and register it in the `return argument` register.
 /// (exclude 0 pid)
 pub fn recycle_and_run_next() {
+    exit_current_proc(0);
+}
+
+/// Terminate the running process with `status` & run other proc.
+pub fn exit_current_proc(status: i32) {
     check_init_runner();
-    unsafe { (*Executer::as_mut_ptr()).t_return() };
+    unsafe { (*Executer::as_mut_ptr()).t_return(status) };
 }
 pub fn run_next_proc() {
     check_init_runner();
     unsafe { (*Executer::as_mut_ptr()).run_next() };
 }
 
+/// Sleep the running process until [`wake_up`] with `chan`. (Other processes run meanwhile.)
+pub fn sleep(chan: WaitChannel) {
+    check_init_runner();
+    unsafe { (*Executer::as_mut_ptr()).sleep(chan) };
+}
+
+/// Make all processes sleeping on `chan` runnable.
+pub fn wake_up(chan: WaitChannel) {
+    check_init_runner();
+    let runner = unsafe { &mut *Executer::as_mut_ptr() };
+    for proc in runner.procs.iter_mut() {
+        if proc.state == ProcState::Sleeping(chan) {
+            proc.state = ProcState::Runnable;
+        }
+    }
+}
+
+/// Call `f` with the live(not `Unused`) process of `pid`.
+///
+/// # Return
+/// `None` if there is no such process.
+pub fn with_proc<R>(pid: usize, f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    check_init_runner();
+    let runner = unsafe { &mut *Executer::as_mut_ptr() };
+    runner
+        .procs
+        .iter_mut()
+        .find(|proc| proc.pid == pid && proc.state != ProcState::Unused)
+        .map(f)
+}
+
+/// pid of the running process. `None` before the Executer runs.
+pub fn current_pid() -> Option<usize> {
+    match IS_SET_RUNNER.load(Ordering::Acquire) {
+        true => Some(with_current_proc(|proc| proc.pid)),
+        false => None,
+    }
+}
+
+/// Call `f` with the running process.
+pub fn with_current_proc<R>(f: impl FnOnce(&mut Process) -> R) -> R {
+    check_init_runner();
+    let runner = unsafe { &mut *Executer::as_mut_ptr() };
+    f(&mut runner.procs[runner.running_proc_idx])
+}
+
+/// Print the process table. (for the fatal trap dump)
+pub fn dump_procs() {
+    if !IS_SET_RUNNER.load(Ordering::Acquire) {
+        println!("procs: Executer is not running");
+        return;
+    }
+    let runner = unsafe { &*Executer::as_mut_ptr() };
+    println!(
+        "procs: current pid={}",
+        runner.procs[runner.running_proc_idx].pid
+    );
+    for (idx, proc) in runner.procs.iter().enumerate() {
+        println!(
+            "  {} pid={} state={:?} personality={:?} page_table={:#010x} ctx.sp={:#010x} trace={}",
+            if idx == runner.running_proc_idx {
+                '*'
+            } else {
+                ' '
+            },
+            proc.pid,
+            proc.state,
+            proc.personality,
+            proc.page_table,
+            proc.ctx.sp,
+            proc.trace
+        );
+    }
+}
+
+/// Options given at spawn time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpawnOptions {
+    /// Trace the syscalls from the first one. (see [`crate::strace`])
+    pub trace: bool,
+}
+
 /// Process Runner
 #[derive(Debug)]
 pub struct Executer {
@@ -75,13 +171,38 @@ impl Executer {
     }
 
     /// Push task to task queue.
-    pub fn push(&mut self, app_range: (usize, usize)) {
+    ///
+    /// - ELF image: loaded by segments. The personality is selected by `e_ident[EI_OSABI]`.
+    ///   - `ELFOSABI_NONE`, `ELFOSABI_LINUX`: Linux
+    ///   - others(e.g. `ELFOSABI_STANDALONE`): Native
+    /// - Others: flat binary for this OS, copied to `USER_BASE`.
+    pub fn push(&mut self, app_range: (usize, usize), options: SpawnOptions) {
         let unused_proc = self
             .procs
             .iter_mut()
             .find(|proc| proc.state == ProcState::Unused)
             .expect("no free process slots");
 
+        let root_ppn = alloc_pages(1).into();
+        ident_map_in_kernel(root_ppn); // All the same kernel code is assigned to the virtual address of each process.
+        let image = unsafe {
+            core::slice::from_raw_parts(app_range.0 as *const u8, app_range.1 - app_range.0)
+        };
+        let (entry, user_sp) = match elf::is_elf(image) {
+            true => match unused_proc.load_elf(root_ppn, image) {
+                Ok(entry_sp) => entry_sp,
+                Err(err) => {
+                    error!("failed to load ELF app: {:?}", err);
+                    return;
+                }
+            },
+            false => {
+                map_one_app(root_ppn, app_range.0, app_range.1);
+                unused_proc.personality = Personality::Native;
+                (USER_BASE, 0)
+            }
+        };
+
         unsafe {
             // calculate stack end field but it's stack start.
             let stack_start_ptr = unused_proc.stack.as_mut_ptr().add(unused_proc.stack.len());
@@ -91,12 +212,13 @@ impl Executer {
             unused_proc.ctx.ra = recycle_and_run_next as usize;
             unused_proc.ctx.current_pc = crate::trap::user_entry as usize;
             unused_proc.ctx.sp = stack_start_ptr.sub(32) as usize;
-
-            let root_ppn = alloc_pages(1).into();
-            ident_map_in_kernel(root_ppn); // All the same kernel code is assigned to the virtual address of each process.
-            map_one_app(root_ppn, app_range.0, app_range.1);
-            unused_proc.page_table = root_ppn;
+            // Passed to `user_entry`
+            unused_proc.ctx.s0 = entry;
+            unused_proc.ctx.s1 = user_sp;
         }
+        unused_proc.page_table = root_ppn;
+        unused_proc.fds = FdTable::stdio();
+        unused_proc.trace = options.trace;
         unused_proc.state = ProcState::Runnable;
     }
 
@@ -110,19 +232,39 @@ impl Executer {
             }
             false => panic!("Only one Executer can exist."),
         }
-        while self.run_next() {}
+        loop {
+            if self.run_next() {
+                continue;
+            }
+            // Nothing runnable. Wait for an interrupt to wake up a sleeping process.
+            let is_sleeping = |proc: &Process| matches!(proc.state, ProcState::Sleeping(_));
+            if !self.procs.iter().any(is_sleeping) {
+                break;
+            }
+            interrupt::wait_for_interrupt();
+        }
     }
 
     /// Recycle completed proc & run other proc.
     /// - This function is intended to be called after task completion.
-    pub(self) fn t_return(&mut self) {
+    pub(self) fn t_return(&mut self, status: i32) {
         if self.running_proc_idx != 0 {
-            self.procs[self.running_proc_idx].state = ProcState::Unused;
-            println!("process {} exit", self.running_proc_idx);
+            let proc = &mut self.procs[self.running_proc_idx];
+            proc.state = ProcState::Unused;
+            proc.fds.clear();
+            if proc.trace {
+                strace::log_exit(proc.pid, status);
+            }
+            info!("process {} exit (status {})", self.running_proc_idx, status);
             self.run_next();
         }
     }
 
+    fn sleep(&mut self, chan: WaitChannel) {
+        self.procs[self.running_proc_idx].state = ProcState::Sleeping(chan);
+        self.run_next();
+    }
+
     /// Yield process.
     ///
     /// # Return
@@ -139,7 +281,7 @@ impl Executer {
         }
 
         // 2. change tasks state: prev = Runnable, next = Running
-        if self.procs[self.running_proc_idx].state != ProcState::Unused {
+        if self.procs[self.running_proc_idx].state == ProcState::Running {
             self.procs[self.running_proc_idx].state = ProcState::Runnable;
         }
         self.procs[next_idx].state = ProcState::Running;
@@ -164,17 +306,34 @@ impl Executer {
     }
 }
 
+/// Which system call interface the process speaks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Personality {
+    /// This OS's own (`abi` crate)
+    #[default]
+    Native,
+    /// Linux rv32 (see [`crate::linux`])
+    Linux,
+}
+
+/// What a sleeping process waits for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitChannel {
+    /// Console input (see [`crate::tty::read`])
+    ConsoleInput,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 enum ProcState {
     Unused,
     Running,
     Runnable,
+    Sleeping(WaitChannel),
 }
 
 /// # PCB: Process Control Block)
 #[derive(Clone, Debug)]
 pub struct Process {
-    #[allow(unused)]
     pid: usize,
     state: ProcState,
     /// root ppn to pageTable
@@ -188,6 +347,17 @@ pub struct Process {
     /// - Others.
     stack: [u8; PROC_STACK_LEN],
     ctx: ProcContext,
+    personality: Personality,
+    /// Start of the heap (page aligned end of the loaded image)
+    brk_start: usize,
+    /// Current program break
+    brk: usize,
+    /// Bottom of the anonymous mmap region (grows down)
+    mmap_top: usize,
+    /// Open files
+    fds: FdTable,
+    /// Log syscalls(strace)
+    trace: bool,
 }
 
 impl Default for Process {
@@ -198,6 +368,12 @@ impl Default for Process {
             page_table: Default::default(),
             stack: [0; PROC_STACK_LEN],
             ctx: Default::default(),
+            personality: Default::default(),
+            brk_start: 0,
+            brk: 0,
+            mmap_top: 0,
+            fds: FdTable::new(),
+            trace: false,
         }
     }
 }
@@ -210,7 +386,111 @@ impl Process {
             page_table: 0,
             stack: [0; PROC_STACK_LEN],
             ctx: ProcContext::new(),
+            personality: Personality::Native,
+            brk_start: 0,
+            brk: 0,
+            mmap_top: 0,
+            fds: FdTable::new(),
+            trace: false,
+        }
+    }
+
+    pub fn pid(&self) -> usize {
+        self.pid
+    }
+
+    pub fn personality(&self) -> Personality {
+        self.personality
+    }
+
+    pub fn is_traced(&self) -> bool {
+        self.trace
+    }
+
+    pub fn set_trace(&mut self, enable: bool) {
+        self.trace = enable;
+    }
+
+    pub fn fds(&mut self) -> &mut FdTable {
+        &mut self.fds
+    }
+
+    /// Physical address of the root page table
+    pub fn page_table(&self) -> usize {
+        self.page_table
+    }
+
+    /// Load ELF segments & decide the personality.
+    ///
+    /// # Return
+    /// (entry pc, initial user sp(0: the program sets it itself))
+    fn load_elf(&mut self, root_ppn: usize, image: &[u8]) -> Result<(usize, usize), elf::ElfError> {
+        let elf = Elf::parse(image)?;
+        let loaded = elf.load(root_ppn, linux::USER_END)?;
+        self.brk_start = loaded.end;
+        self.brk = loaded.end;
+        self.mmap_top = linux::MMAP_TOP;
+        self.personality = match elf.os_abi() {
+            ELFOSABI_NONE | ELFOSABI_LINUX => Personality::Linux,
+            _ => Personality::Native,
+        };
+
+        let user_sp = match self.personality {
+            Personality::Linux => linux::init_stack(root_ppn, &loaded)?,
+            Personality::Native => 0,
+        };
+        Ok((loaded.entry, user_sp))
+    }
+
+    /// Change the program break.(Linux `brk` semantics)
+    ///
+    /// # Return
+    /// New program break. On failure(including the heap over the MMIO registers and out of
+    /// memory), the current one.
+    pub fn set_brk(&mut self, new_brk: usize) -> usize {
+        if new_brk < self.brk_start
+            || new_brk > self.brk_start + linux::BRK_MAX
+            || overlaps_mmio(self.brk_start, new_brk)
+        {
+            return self.brk;
+        }
+        if new_brk > self.brk
+            && map_user_pages(
+                self.page_table,
+                self.brk,
+                new_brk,
+                PAGE_R | PAGE_W,
+                |_, _| {},
+            )
+            .is_err()
+        {
+            return self.brk;
+        }
+        self.brk = new_brk;
+        self.brk
+    }
+
+    /// Map 0 filled anonymous pages.
+    ///
+    /// # Return
+    /// Start address. `None` if the mmap region collides with the heap or the MMIO registers, or
+    /// out of memory.
+    pub fn mmap_anonymous(&mut self, len: usize) -> Option<usize> {
+        let len = align_up(len, PAGE_SIZE);
+        let start = self.mmap_top.checked_sub(len)?;
+        if start < self.brk_start + linux::BRK_MAX || overlaps_mmio(start, self.mmap_top) {
+            return None;
         }
+        map_user_pages(
+            self.page_table,
+            start,
+            self.mmap_top,
+            PAGE_R | PAGE_W,
+            |_, _| {},
+        )
+        .ok()?;
+        self.mmap_top = start;
+        Some(start)
     }
 
     /// set and enable virtual addressing mode, save sp to sscratch
@@ -0,0 +1,86 @@
+//! Fixed size FIFO queue without allocation.
+
+pub struct RingBuffer<T: Copy, const N: usize> {
+    buf: [T; N],
+    /// Index of the oldest element
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    /// # Parameters
+    /// - fill: initial value of the (unused) slots
+    pub const fn new(fill: T) -> Self {
+        Self {
+            buf: [fill; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Append `value` to the tail.
+    ///
+    /// # Return
+    /// `false` if full. (`value` is dropped.)
+    pub fn push(&mut self, value: T) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.buf[(self.head + self.len) % N] = value;
+        self.len += 1;
+        true
+    }
+
+    /// Append `value` to the tail. If full, the oldest value is dropped.
+    pub fn push_overwrite(&mut self, value: T) {
+        if self.is_full() {
+            self.pop();
+        }
+        self.push(value);
+    }
+
+    /// Oldest to newest, without removing.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len).map(|i| self.buf[(self.head + i) % N])
+    }
+
+    /// Contents as (older part, newer part). Oldest to newest when chained.
+    pub fn as_slices(&self) -> (&[T], &[T]) {
+        let end = self.head + self.len;
+        match end <= N {
+            true => (&self.buf[self.head..end], &[]),
+            false => (&self.buf[self.head..], &self.buf[..end - N]),
+        }
+    }
+
+    /// The oldest value, without removing.
+    pub fn peek(&self) -> Option<T> {
+        match self.is_empty() {
+            true => None,
+            false => Some(self.buf[self.head]),
+        }
+    }
+
+    /// Take the oldest value.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let value = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(value)
+    }
+}
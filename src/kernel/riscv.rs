@@ -7,6 +7,12 @@ pub fn raise_exception() {
     unsafe { asm!("unimp") }
 }
 
+/// Flush all TLB entries. Needed after the page table of the running process is changed.
+#[inline]
+pub fn sfence_vma_all() {
+    unsafe { asm!("sfence.vma") }
+}
+
 pub mod scause {
     use core::arch::asm;
 
@@ -21,7 +27,7 @@ pub mod scause {
     impl From<usize> for Scause {
         fn from(value: usize) -> Self {
             // We want to check if the most significant bit (32nd bit if 32 bits), which is the interrupt flag, is 1.
-            let is_interrupt = (value & 1 >> (core::mem::size_of::<usize>() - 1)) == 1;
+            let is_interrupt = (value >> (usize::BITS - 1)) == 1;
             match is_interrupt {
                 true => Scause::Interrupt(Interrupt::try_from(value).unwrap_or_default()),
                 false => Scause::Exception(Exception::try_from(value).unwrap_or_default()),
@@ -29,26 +35,31 @@ pub mod scause {
         }
     }
 
-    #[derive(Debug, Default)]
+    /// The discriminant is the exception code(= vector table index).
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
     pub enum Interrupt {
-        UserSoftware,
-        SupervisorSoftware,
-        UserTimer,
-        SupervisorTimer,
+        UserSoftware = 0,
+        SupervisorSoftware = 1,
+        UserTimer = 4,
+        SupervisorTimer = 5,
+        UserExternal = 8,
+        SupervisorExternal = 9,
         #[default]
-        Unknown,
+        Unknown = 16,
     }
 
     impl TryFrom<usize> for Interrupt {
         type Error = Interrupt;
         fn try_from(value: usize) -> Result<Self, Self::Error> {
             // Clears the most significant bit (interrupt flag) to convert to enum in match expression.
-            let value = value & (1 << (core::mem::size_of::<usize>() - 1));
+            let value = value & !(1 << (usize::BITS - 1));
             Ok(match value {
                 0 => Interrupt::UserSoftware,
                 1 => Interrupt::SupervisorSoftware,
                 4 => Interrupt::UserTimer,
                 5 => Interrupt::SupervisorTimer,
+                8 => Interrupt::UserExternal,
+                9 => Interrupt::SupervisorExternal,
                 _ => return Err(Interrupt::Unknown),
             })
         }
@@ -139,6 +150,18 @@ pub mod sepc {
     }
 }
 
+pub mod satp {
+    use core::arch::asm;
+
+    /// MODE(1bit) | ASID(9bit) | PPN(22bit)
+    #[inline]
+    pub unsafe fn read() -> usize {
+        let value: usize;
+        asm!("csrr {}, satp", out(reg) value);
+        value
+    }
+}
+
 pub mod stvec {
     use core::arch::asm;
 
@@ -157,6 +180,15 @@ pub mod stvec {
 pub mod sstatus {
     use core::arch::asm;
 
+    /// Supervisor interrupt enable
+    pub const SIE: usize = 1 << 1;
+    /// Supervisor previous interrupt enable
+    pub const SPIE: usize = 1 << 5;
+    /// Supervisor previous privilege(1: trapped from S-Mode)
+    pub const SPP: usize = 1 << 8;
+    /// permit Supervisor User Memory access
+    pub const SUM: usize = 1 << 18;
+
     #[inline]
     pub unsafe fn read() -> usize {
         let mut value;
@@ -168,4 +200,92 @@ pub mod sstatus {
     pub unsafe fn write(value: usize) {
         asm!("csrw sstatus, {}", in(reg) value);
     }
+
+    /// Set the bits of `mask`.
+    #[inline]
+    pub unsafe fn set(mask: usize) {
+        asm!("csrs sstatus, {}", in(reg) mask);
+    }
+
+    /// Clear the bits of `mask`.
+    #[inline]
+    pub unsafe fn clear(mask: usize) {
+        asm!("csrc sstatus, {}", in(reg) mask);
+    }
+}
+
+/// Supervisor interrupt enable register
+pub mod sie {
+    use core::arch::asm;
+
+    /// Supervisor software interrupt enable
+    pub const SSIE: usize = 1 << 1;
+    /// Supervisor timer interrupt enable
+    pub const STIE: usize = 1 << 5;
+    /// Supervisor external interrupt enable
+    pub const SEIE: usize = 1 << 9;
+
+    #[inline]
+    pub unsafe fn read() -> usize {
+        let value: usize;
+        asm!("csrr {}, sie", out(reg) value);
+        value
+    }
+
+    /// Set the bits of `mask`.
+    #[inline]
+    pub unsafe fn set(mask: usize) {
+        asm!("csrs sie, {}", in(reg) mask);
+    }
+
+    /// Clear the bits of `mask`.
+    #[inline]
+    pub unsafe fn clear(mask: usize) {
+        asm!("csrc sie, {}", in(reg) mask);
+    }
+}
+
+/// Supervisor interrupt pending register
+pub mod sip {
+    use core::arch::asm;
+
+    /// Supervisor external interrupt pending
+    pub const SEIP: usize = 1 << 9;
+
+    #[inline]
+    pub unsafe fn read() -> usize {
+        let value: usize;
+        asm!("csrr {}, sip", out(reg) value);
+        value
+    }
+}
+
+/// `time` CSR(= mtime shadow)
+pub mod time {
+    use core::arch::asm;
+
+    /// Ticks per second of `time` on QEMU virt machine.(timebase-frequency in device tree)
+    pub const TIMEBASE_FREQ: u64 = 10_000_000;
+
+    /// Read 64bit `time` on RV32. (retry if the upper half changes while reading)
+    #[inline]
+    pub fn read() -> u64 {
+        loop {
+            let (hi, lo, hi2): (u32, u32, u32);
+            unsafe {
+                asm!(
+                    "rdtimeh {0}",
+                    "rdtime {1}",
+                    "rdtimeh {2}",
+                    out(reg) hi,
+                    out(reg) lo,
+                    out(reg) hi2,
+                    options(nomem, nostack)
+                );
+            }
+            if hi == hi2 {
+                return ((hi as u64) << 32) | lo as u64;
+            }
+        }
+    }
 }
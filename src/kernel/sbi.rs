@@ -1,4 +1,12 @@
+//! SBI(Supervisor Binary Interface) client.
+//!
+//! Each extension is probed once by [`init`], and the wrappers fall back to the legacy(v0.1) calls
+//! when the extension is not available.
+//! - ref: https://github.com/riscv-non-isa/riscv-sbi-doc
+use crate::info;
 use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Debug)]
 pub struct SbiRet {
@@ -6,8 +14,66 @@ pub struct SbiRet {
     pub value: usize,
 }
 
+impl SbiRet {
+    pub fn into_result(self) -> Result<usize, SbiError> {
+        match self.error {
+            0 => Ok(self.value),
+            error => Err(SbiError::from_code(error)),
+        }
+    }
+}
+
+/// Standard SBI error codes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SbiError {
+    Failed,
+    NotSupported,
+    InvalidParam,
+    Denied,
+    InvalidAddress,
+    AlreadyAvailable,
+    AlreadyStarted,
+    AlreadyStopped,
+    NoShmem,
+    InvalidState,
+    BadRange,
+    Timeout,
+    Io,
+    /// Not defined by the spec
+    Unknown(isize),
+}
+
+impl SbiError {
+    pub fn from_code(code: isize) -> Self {
+        match code {
+            -1 => Self::Failed,
+            -2 => Self::NotSupported,
+            -3 => Self::InvalidParam,
+            -4 => Self::Denied,
+            -5 => Self::InvalidAddress,
+            -6 => Self::AlreadyAvailable,
+            -7 => Self::AlreadyStarted,
+            -8 => Self::AlreadyStopped,
+            -9 => Self::NoShmem,
+            -10 => Self::InvalidState,
+            -11 => Self::BadRange,
+            -12 => Self::Timeout,
+            -13 => Self::Io,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for SbiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// # Parameters
+/// `args`: a0..=a5, a6(FID), a7(EID)
 #[inline(always)]
-pub(crate) fn sbi_call(args: [usize; 8]) -> SbiRet {
+fn sbi_call(args: [usize; 8]) -> SbiRet {
     let mut error;
     let mut value;
     unsafe {
@@ -26,3 +92,450 @@ pub(crate) fn sbi_call(args: [usize; 8]) -> SbiRet {
 
     SbiRet { error, value }
 }
+
+/// SBI v0.2+ call
+#[inline(always)]
+fn ecall(eid: usize, fid: usize, args: [usize; 6]) -> Result<usize, SbiError> {
+    let [a0, a1, a2, a3, a4, a5] = args;
+    sbi_call([a0, a1, a2, a3, a4, a5, fid, eid]).into_result()
+}
+
+/// Legacy(v0.1) call: No FID, the return value is in a0.
+#[inline(always)]
+fn legacy_call(eid: usize, args: [usize; 4]) -> isize {
+    sbi_call([args[0], args[1], args[2], args[3], 0, 0, 0, eid]).error
+}
+
+/// Extensions used by this kernel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extension {
+    Time,
+    Ipi,
+    Rfence,
+    Hsm,
+    Srst,
+    Dbcn,
+}
+
+impl Extension {
+    const ALL: [Self; 6] = [
+        Self::Time,
+        Self::Ipi,
+        Self::Rfence,
+        Self::Hsm,
+        Self::Srst,
+        Self::Dbcn,
+    ];
+
+    /// Extension ID
+    pub const fn eid(self) -> usize {
+        match self {
+            Self::Time => time::EID,
+            Self::Ipi => ipi::EID,
+            Self::Rfence => rfence::EID,
+            Self::Hsm => hsm::EID,
+            Self::Srst => srst::EID,
+            Self::Dbcn => dbcn::EID,
+        }
+    }
+}
+
+/// Bit set of available [`Extension`]s. (index: declaration order)
+static AVAILABLE_EXTENSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Probe all extensions. Until this is called, only the legacy calls are used.
+pub fn init() {
+    let version = match base::spec_version() {
+        // The Base extension(& probing) exists since v0.2.
+        Ok(version) if version >= base::SpecVersion::V0_2 => version,
+        _ => {
+            info!("SBI v0.1: use legacy calls");
+            return;
+        }
+    };
+
+    let mut available = 0;
+    for (bit, ext) in Extension::ALL.iter().enumerate() {
+        if base::probe_extension(ext.eid()).is_ok_and(|value| value != 0) {
+            available |= 1 << bit;
+        }
+    }
+    AVAILABLE_EXTENSIONS.store(available, Ordering::Release);
+
+    let impl_id = base::impl_id().unwrap_or(usize::MAX);
+    info!(
+        "SBI {} {}({:#x}) extensions: {:?}",
+        version,
+        base::impl_name(impl_id),
+        base::impl_version().unwrap_or(0),
+        AvailableExtensions
+    );
+}
+
+/// Debug print of the available extensions
+struct AvailableExtensions;
+
+impl fmt::Debug for AvailableExtensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let available = Extension::ALL.iter().filter(|ext| is_available(**ext));
+        f.debug_list().entries(available).finish()
+    }
+}
+
+pub fn is_available(ext: Extension) -> bool {
+    let bit = Extension::ALL.iter().position(|e| *e == ext).unwrap();
+    AVAILABLE_EXTENSIONS.load(Ordering::Acquire) & (1 << bit) != 0
+}
+
+/// Set of harts: `hart_mask` bits from `hart_mask_base`.
+#[derive(Clone, Copy, Debug)]
+pub struct HartMask {
+    pub mask: usize,
+    pub base: usize,
+}
+
+impl HartMask {
+    /// All available harts (`hart_mask_base == -1`)
+    pub const ALL: Self = Self {
+        mask: 0,
+        base: usize::MAX,
+    };
+
+    pub const fn from_hart(hartid: usize) -> Self {
+        Self {
+            mask: 1,
+            base: hartid,
+        }
+    }
+
+    /// Legacy calls take a pointer to the mask(null: all harts) instead.
+    fn with_legacy_mask<R>(&self, f: impl FnOnce(usize) -> R) -> R {
+        if self.base == usize::MAX {
+            return f(0);
+        }
+        let mask = self.mask.checked_shl(self.base as u32).unwrap_or(0);
+        f(&mask as *const usize as usize)
+    }
+}
+
+/// Base extension
+pub mod base {
+    use super::{ecall, SbiError};
+    use core::fmt;
+
+    pub const EID: usize = 0x10;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct SpecVersion {
+        pub major: usize,
+        pub minor: usize,
+    }
+
+    impl SpecVersion {
+        pub const V0_2: Self = Self { major: 0, minor: 2 };
+    }
+
+    impl fmt::Display for SpecVersion {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "v{}.{}", self.major, self.minor)
+        }
+    }
+
+    /// # Errors
+    /// SBI v0.1 has no Base extension.
+    pub fn spec_version() -> Result<SpecVersion, SbiError> {
+        let value = ecall(EID, 0, [0; 6])?;
+        Ok(SpecVersion {
+            major: (value >> 24) & 0x7f,
+            minor: value & 0xff_ffff,
+        })
+    }
+
+    pub fn impl_id() -> Result<usize, SbiError> {
+        ecall(EID, 1, [0; 6])
+    }
+
+    pub fn impl_version() -> Result<usize, SbiError> {
+        ecall(EID, 2, [0; 6])
+    }
+
+    /// # Return
+    /// 0 if the extension is not available, otherwise an extension specific non-zero value.
+    pub fn probe_extension(eid: usize) -> Result<usize, SbiError> {
+        ecall(EID, 3, [eid, 0, 0, 0, 0, 0])
+    }
+
+    pub fn mvendorid() -> Result<usize, SbiError> {
+        ecall(EID, 4, [0; 6])
+    }
+
+    pub fn marchid() -> Result<usize, SbiError> {
+        ecall(EID, 5, [0; 6])
+    }
+
+    pub fn mimpid() -> Result<usize, SbiError> {
+        ecall(EID, 6, [0; 6])
+    }
+
+    /// Name of `impl_id`
+    pub fn impl_name(impl_id: usize) -> &'static str {
+        match impl_id {
+            0 => "BBL",
+            1 => "OpenSBI",
+            2 => "Xvisor",
+            3 => "KVM",
+            4 => "RustSBI",
+            5 => "Diosix",
+            6 => "Coffer",
+            7 => "Xen",
+            8 => "PolarFire HSS",
+            9 => "coreboot",
+            10 => "oreboot",
+            _ => "unknown",
+        }
+    }
+}
+
+/// Timer extension
+pub mod time {
+    use super::{ecall, is_available, legacy_call, Extension, SbiError};
+
+    pub const EID: usize = 0x5449_4D45;
+    const LEGACY_SET_TIMER: usize = 0x00;
+
+    /// Program the next timer interrupt at `stime_value`(`time` CSR value).
+    pub fn set_timer(stime_value: u64) -> Result<(), SbiError> {
+        let (low, high) = (stime_value as usize, (stime_value >> 32) as usize);
+        match is_available(Extension::Time) {
+            true => ecall(EID, 0, [low, high, 0, 0, 0, 0]).map(|_| ()),
+            false => {
+                legacy_call(LEGACY_SET_TIMER, [low, high, 0, 0]);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// IPI extension
+pub mod ipi {
+    use super::{ecall, is_available, legacy_call, Extension, HartMask, SbiError};
+
+    pub const EID: usize = 0x73_5049;
+    const LEGACY_SEND_IPI: usize = 0x04;
+
+    /// Send a supervisor software interrupt to `harts`.
+    pub fn send_ipi(harts: HartMask) -> Result<(), SbiError> {
+        match is_available(Extension::Ipi) {
+            true => ecall(EID, 0, [harts.mask, harts.base, 0, 0, 0, 0]).map(|_| ()),
+            false => harts.with_legacy_mask(|mask_ptr| {
+                match legacy_call(LEGACY_SEND_IPI, [mask_ptr, 0, 0, 0]) {
+                    0 => Ok(()),
+                    error => Err(SbiError::from_code(error)),
+                }
+            }),
+        }
+    }
+}
+
+/// Remote fence extension
+pub mod rfence {
+    use super::{ecall, is_available, legacy_call, Extension, HartMask, SbiError};
+
+    pub const EID: usize = 0x5246_4E43;
+    const LEGACY_REMOTE_FENCE_I: usize = 0x05;
+    const LEGACY_REMOTE_SFENCE_VMA: usize = 0x06;
+    const LEGACY_REMOTE_SFENCE_VMA_ASID: usize = 0x07;
+
+    fn call(
+        fid: usize,
+        legacy_eid: usize,
+        harts: HartMask,
+        args: [usize; 3],
+    ) -> Result<(), SbiError> {
+        match is_available(Extension::Rfence) {
+            true => {
+                let [a2, a3, a4] = args;
+                ecall(EID, fid, [harts.mask, harts.base, a2, a3, a4, 0]).map(|_| ())
+            }
+            false => harts.with_legacy_mask(|mask_ptr| {
+                match legacy_call(legacy_eid, [mask_ptr, args[0], args[1], args[2]]) {
+                    0 => Ok(()),
+                    error => Err(SbiError::from_code(error)),
+                }
+            }),
+        }
+    }
+
+    /// `fence.i` on `harts`
+    pub fn remote_fence_i(harts: HartMask) -> Result<(), SbiError> {
+        call(0, LEGACY_REMOTE_FENCE_I, harts, [0; 3])
+    }
+
+    /// `sfence.vma` of `[start, start + size)` on `harts`
+    pub fn remote_sfence_vma(harts: HartMask, start: usize, size: usize) -> Result<(), SbiError> {
+        call(1, LEGACY_REMOTE_SFENCE_VMA, harts, [start, size, 0])
+    }
+
+    /// `sfence.vma` of `[start, start + size)` & `asid` on `harts`
+    pub fn remote_sfence_vma_asid(
+        harts: HartMask,
+        start: usize,
+        size: usize,
+        asid: usize,
+    ) -> Result<(), SbiError> {
+        call(2, LEGACY_REMOTE_SFENCE_VMA_ASID, harts, [start, size, asid])
+    }
+}
+
+/// Hart state management extension (No legacy version)
+pub mod hsm {
+    use super::{ecall, is_available, Extension, SbiError};
+
+    pub const EID: usize = 0x48_534D;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum HartState {
+        Started,
+        Stopped,
+        StartPending,
+        StopPending,
+        Suspended,
+        SuspendPending,
+        ResumePending,
+    }
+
+    fn call(fid: usize, args: [usize; 6]) -> Result<usize, SbiError> {
+        match is_available(Extension::Hsm) {
+            true => ecall(EID, fid, args),
+            false => Err(SbiError::NotSupported),
+        }
+    }
+
+    /// Start `hartid` in S-Mode at the physical address `start_addr`. (`a1 = opaque`)
+    pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> Result<(), SbiError> {
+        call(0, [hartid, start_addr, opaque, 0, 0, 0]).map(|_| ())
+    }
+
+    /// Stop the calling hart. (Never returns on success)
+    pub fn hart_stop() -> Result<(), SbiError> {
+        call(1, [0; 6]).map(|_| ())
+    }
+
+    pub fn hart_get_status(hartid: usize) -> Result<HartState, SbiError> {
+        Ok(match call(2, [hartid, 0, 0, 0, 0, 0])? {
+            0 => HartState::Started,
+            1 => HartState::Stopped,
+            2 => HartState::StartPending,
+            3 => HartState::StopPending,
+            4 => HartState::Suspended,
+            5 => HartState::SuspendPending,
+            6 => HartState::ResumePending,
+            _ => return Err(SbiError::Failed),
+        })
+    }
+
+    /// # Parameters
+    /// - suspend_type: 0 is the default retentive suspend
+    pub fn hart_suspend(
+        suspend_type: u32,
+        resume_addr: usize,
+        opaque: usize,
+    ) -> Result<(), SbiError> {
+        call(3, [suspend_type as usize, resume_addr, opaque, 0, 0, 0]).map(|_| ())
+    }
+}
+
+/// System reset extension
+pub mod srst {
+    use super::{ecall, is_available, legacy_call, Extension, SbiError};
+
+    pub const EID: usize = 0x5352_5354;
+    const LEGACY_SHUTDOWN: usize = 0x08;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum ResetType {
+        Shutdown = 0,
+        ColdReboot = 1,
+        WarmReboot = 2,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum ResetReason {
+        NoReason = 0,
+        SystemFailure = 1,
+    }
+
+    /// Never returns on success.
+    ///
+    /// # Return
+    /// The error on failure.
+    /// - NotSupported: reboot without the SRST extension (Legacy has shutdown only.)
+    pub fn system_reset(reset_type: ResetType, reason: ResetReason) -> SbiError {
+        match is_available(Extension::Srst) {
+            true => ecall(EID, 0, [reset_type as usize, reason as usize, 0, 0, 0, 0])
+                .err()
+                .unwrap_or(SbiError::Failed),
+            false if reset_type == ResetType::Shutdown => {
+                legacy_call(LEGACY_SHUTDOWN, [0; 4]);
+                SbiError::Failed
+            }
+            false => SbiError::NotSupported,
+        }
+    }
+}
+
+/// Debug console extension
+pub mod dbcn {
+    use super::{ecall, is_available, legacy_call, Extension, SbiError};
+
+    pub const EID: usize = 0x4442_434E;
+    const LEGACY_CONSOLE_PUTCHAR: usize = 0x01;
+    const LEGACY_CONSOLE_GETCHAR: usize = 0x02;
+
+    /// Write bytes of the physical address. (The kernel is identity mapped.)
+    ///
+    /// # Return
+    /// Written bytes. (May be less than `bytes.len()`)
+    pub fn console_write(bytes: &[u8]) -> Result<usize, SbiError> {
+        match is_available(Extension::Dbcn) {
+            true => ecall(EID, 0, [bytes.len(), bytes.as_ptr() as usize, 0, 0, 0, 0]),
+            false => {
+                for byte in bytes {
+                    legacy_call(LEGACY_CONSOLE_PUTCHAR, [*byte as usize, 0, 0, 0]);
+                }
+                Ok(bytes.len())
+            }
+        }
+    }
+
+    /// Read available bytes without blocking.
+    ///
+    /// # Return
+    /// Read bytes. (0 if no input)
+    pub fn console_read(buf: &mut [u8]) -> Result<usize, SbiError> {
+        match is_available(Extension::Dbcn) {
+            true => ecall(EID, 1, [buf.len(), buf.as_mut_ptr() as usize, 0, 0, 0, 0]),
+            false => {
+                let mut read = 0;
+                for byte in buf.iter_mut() {
+                    match legacy_call(LEGACY_CONSOLE_GETCHAR, [0; 4]) {
+                        ch if ch >= 0 => *byte = ch as u8,
+                        _ => break,
+                    }
+                    read += 1;
+                }
+                Ok(read)
+            }
+        }
+    }
+
+    pub fn console_write_byte(byte: u8) -> Result<(), SbiError> {
+        match is_available(Extension::Dbcn) {
+            true => ecall(EID, 2, [byte as usize, 0, 0, 0, 0, 0]).map(|_| ()),
+            false => {
+                legacy_call(LEGACY_CONSOLE_PUTCHAR, [byte as usize, 0, 0, 0]);
+                Ok(())
+            }
+        }
+    }
+}
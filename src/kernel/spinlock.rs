@@ -0,0 +1,79 @@
+//! Spin lock that also disables S-Mode interrupts while held.
+//!
+//! Interrupts are disabled before spinning, so an interrupt handler on the same hart can't
+//! deadlock by taking the lock held by the code it interrupted.
+use crate::riscv::sstatus;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Disable interrupts & spin until the lock is taken.
+    ///
+    /// The interrupt enable(`sstatus.SIE`) is restored when the guard is dropped.
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let sie = unsafe { sstatus::read() } & sstatus::SIE;
+        unsafe { sstatus::clear(sstatus::SIE) };
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        SpinLockGuard { lock: self, sie }
+    }
+
+    /// Release the lock regardless of the holder.
+    ///
+    /// # Safety
+    /// The holder must never touch the data again. (e.g. the holder panicked, and it's only for
+    /// the panic message)
+    pub unsafe fn force_unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+    /// `sstatus.SIE` before locking
+    sie: usize,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        if self.sie != 0 {
+            unsafe { sstatus::set(sstatus::SIE) };
+        }
+    }
+}
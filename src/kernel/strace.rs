@@ -0,0 +1,56 @@
+//! Strace-style syscall tracing per process.
+//!
+//! Enabled by the `trace` syscall or `SpawnOptions::trace`. Output example:
+//! ```txt
+//! [strace pid=1] put_char(ch='>') = 0 <12us>
+//! [strace pid=1] get_char() = -1 EFAULT (Bad address) <3us>
+//! [strace pid=1] +++ exited with 0 +++
+//! ```
+use crate::println;
+use abi::Errno;
+use core::fmt;
+use kernel::riscv::time;
+
+/// Log one finished syscall.
+///
+/// # Parameters
+/// - args: decoded arguments (written between the parentheses)
+/// - start_ticks: `time` when the syscall was entered
+pub fn log_syscall(
+    pid: usize,
+    name: &str,
+    args: impl fmt::Display,
+    result: &Result<usize, Errno>,
+    start_ticks: u64,
+) {
+    let elapsed_us = (time::read() - start_ticks) * 1_000_000 / time::TIMEBASE_FREQ;
+    match result {
+        Ok(ret) => println!(
+            "[strace pid={}] {}({}) = {} <{}us>",
+            pid, name, args, *ret as isize, elapsed_us
+        ),
+        Err(errno) => println!(
+            "[strace pid={}] {}({}) = -1 {:?} ({}) <{}us>",
+            pid,
+            name,
+            args,
+            errno,
+            errno.description(),
+            elapsed_us
+        ),
+    }
+}
+
+/// Log the exit of a traced process. (The exit syscall never returns.)
+pub fn log_exit(pid: usize, status: i32) {
+    println!("[strace pid={}] +++ exited with {} +++", pid, status);
+}
+
+/// Display the arguments with a formatter function.
+pub struct FmtArgs<F: Fn(&mut fmt::Formatter<'_>) -> fmt::Result>(pub F);
+
+impl<F: Fn(&mut fmt::Formatter<'_>) -> fmt::Result> fmt::Display for FmtArgs<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (self.0)(f)
+    }
+}
@@ -0,0 +1,246 @@
+//! Table-driven system call dispatch.
+//!
+//! The system call list(numbers & signatures) is defined in the `abi` crate.
+//! Implement the new method of [`Syscalls`] for [`KernelSyscalls`] to add a system call.
+use crate::drivers::test_finisher;
+use crate::fs::{self, current_file, PATH_MAX};
+use crate::info;
+use crate::linux;
+use crate::log;
+use crate::power;
+use crate::proc::{exit_current_proc, with_current_proc, with_proc, Personality};
+use crate::sbi::{srst::ResetReason, SbiError};
+use crate::strace::{self, FmtArgs};
+use crate::trap::TrapFrame;
+use crate::tty;
+use crate::uaccess::{copy_str_from_user, copy_to_user, write_user};
+use abi::conv::{decode_call, encode_ret};
+use abi::fs::{Dirent, OpenFlags, Stat, NAME_MAX};
+use abi::{
+    fmt_syscall_args, syscall_name, syscall_table, Errno, RawSyscallFn, Syscalls, UserBuf,
+    UserBufMut, UserCStr,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kernel::riscv::time;
+
+/// Max syscall number + 1
+const SYSCALL_TABLE_LEN: usize = 64;
+/// Registered syscall ptr per syscall number. (0 == not registered => ENOSYS)
+static SYSCALL_TABLE: [AtomicUsize; SYSCALL_TABLE_LEN] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const UNREGISTERED: AtomicUsize = AtomicUsize::new(0);
+    [UNREGISTERED; SYSCALL_TABLE_LEN]
+};
+
+/// Register all system calls defined by `abi`.
+pub fn init() {
+    for desc in syscall_table::<KernelSyscalls>() {
+        register_syscall(desc.sysno, desc.handler);
+    }
+}
+
+/// Hook `handler` to `sysno`.
+///
+/// # Panics
+/// `sysno` is out of the table range.
+pub fn register_syscall(sysno: usize, handler: RawSyscallFn) {
+    assert!(
+        sysno < SYSCALL_TABLE_LEN,
+        "syscall number {sysno} is out of table range"
+    );
+    SYSCALL_TABLE[sysno].store(handler as usize, Ordering::Release);
+}
+
+fn lookup(sysno: usize) -> Option<RawSyscallFn> {
+    let handler_ptr = SYSCALL_TABLE.get(sysno)?.load(Ordering::Acquire);
+    match handler_ptr {
+        0 => None,
+        ptr => Some(unsafe { core::mem::transmute::<usize, RawSyscallFn>(ptr) }),
+    }
+}
+
+/// Decode the syscall from registers by `abi::conv` & write back the result.
+///
+/// Linux personality processes are handled by [`linux::handle_syscall`].
+pub fn handle_syscall(f: &mut TrapFrame) {
+    let (pid, personality, traced) =
+        with_current_proc(|proc| (proc.pid(), proc.personality(), proc.is_traced()));
+    if personality == Personality::Linux {
+        return linux::handle_syscall(f, traced.then_some(pid));
+    }
+
+    let start_ticks = time::read();
+    let (sysno, args) = decode_call(&[f.a0, f.a1, f.a2, f.a3, f.a4, f.a5, f.a6, f.a7]);
+    let result = match lookup(sysno) {
+        Some(handler) => handler(args),
+        None => Err(Errno::ENOSYS),
+    };
+    if traced {
+        strace::log_syscall(
+            pid,
+            syscall_name(sysno).unwrap_or("unknown"),
+            FmtArgs(|f| fmt_syscall_args(sysno, args, f)),
+            &result.map(|ret| ret[0]),
+            start_ticks,
+        );
+    }
+    // Legacy ABI has only a0. (Don't clobber a1)
+    for (reg, value) in [&mut f.a0, &mut f.a1].into_iter().zip(encode_ret(result)) {
+        *reg = value;
+    }
+}
+
+pub struct KernelSyscalls;
+
+impl Syscalls for KernelSyscalls {
+    fn put_char(ch: char) -> Result<(), Errno> {
+        tty::write(ch.encode_utf8(&mut [0; 4]).as_bytes());
+        Ok(())
+    }
+
+    fn get_char() -> Result<u8, Errno> {
+        let mut byte = [0];
+        match tty::read(&mut byte) {
+            0 => Ok(tty::EOT),
+            _ => Ok(byte[0]),
+        }
+    }
+
+    fn exit(status: i32) -> Result<(), Errno> {
+        exit_current_proc(status);
+        Ok(())
+    }
+
+    fn trace(pid: usize, enable: bool) -> Result<(), Errno> {
+        match pid {
+            0 => with_current_proc(|proc| proc.set_trace(enable)),
+            pid => with_proc(pid, |proc| proc.set_trace(enable)).ok_or(Errno::ESRCH)?,
+        };
+        Ok(())
+    }
+
+    fn shutdown() -> Result<(), Errno> {
+        info!("shutdown requested");
+        Err(sbi_errno(power::shutdown(ResetReason::NoReason)))
+    }
+
+    fn reboot() -> Result<(), Errno> {
+        info!("reboot requested");
+        Err(sbi_errno(power::reboot()))
+    }
+
+    fn exit_qemu(code: u16) -> Result<(), Errno> {
+        info!("exit qemu (code {})", code);
+        test_finisher::exit_qemu(code)
+    }
+
+    fn dmesg(buf: UserBufMut) -> Result<usize, Errno> {
+        let len = buf.len.min(log::dmesg_len());
+        let start = log::dmesg_len() - len;
+        // Copy via a small stack buffer. (the kernel stack is small)
+        let mut chunk = [0u8; 256];
+        let mut copied = 0;
+        while copied < len {
+            let n = log::read_dmesg(start + copied, &mut chunk[..(len - copied).min(256)]);
+            copy_to_user(buf.addr + copied, &chunk[..n])?;
+            copied += n;
+        }
+        Ok(len)
+    }
+
+    fn ioctl(fd: usize, request: usize, arg: usize) -> Result<usize, Errno> {
+        current_file(fd)?.ioctl(request, arg)
+    }
+
+    fn write(fd: usize, buf: UserBuf) -> Result<usize, Errno> {
+        current_file(fd)?.write(buf.addr, buf.len)
+    }
+
+    fn read(fd: usize, buf: UserBufMut) -> Result<usize, Errno> {
+        current_file(fd)?.read(buf.addr, buf.len)
+    }
+
+    fn readfile(path: UserCStr, buf: UserBufMut) -> Result<usize, Errno> {
+        let mut path_buf = [0u8; PATH_MAX];
+        let path = copy_str_from_user(&mut path_buf, path.addr)?;
+        fs::open(path, OpenFlags::RDONLY)?.read(buf.addr, buf.len)
+    }
+
+    fn writefile(path: UserCStr, buf: UserBuf) -> Result<usize, Errno> {
+        let mut path_buf = [0u8; PATH_MAX];
+        let path = copy_str_from_user(&mut path_buf, path.addr)?;
+        let flags = OpenFlags::WRONLY | OpenFlags::CREAT | OpenFlags::TRUNC;
+        fs::open(path, flags)?.write(buf.addr, buf.len)
+    }
+
+    fn open(path: UserCStr, flags: OpenFlags) -> Result<usize, Errno> {
+        let mut path_buf = [0u8; PATH_MAX];
+        let path = copy_str_from_user(&mut path_buf, path.addr)?;
+        let file = fs::open(path, flags)?;
+        with_current_proc(|proc| proc.fds().insert(file))
+    }
+
+    fn close(fd: usize) -> Result<(), Errno> {
+        with_current_proc(|proc| proc.fds().remove(fd)).map(|_| ())
+    }
+
+    fn lseek(fd: usize, offset: isize, whence: u32) -> Result<usize, Errno> {
+        current_file(fd)?.seek(offset, whence)
+    }
+
+    fn fstat(fd: usize, stat: UserBufMut) -> Result<(), Errno> {
+        if stat.len != core::mem::size_of::<Stat>() {
+            return Err(Errno::EINVAL);
+        }
+        write_user(stat.addr, current_file(fd)?.stat())
+    }
+
+    fn mkdir(path: UserCStr) -> Result<(), Errno> {
+        let mut path_buf = [0u8; PATH_MAX];
+        fs::mkdir(copy_str_from_user(&mut path_buf, path.addr)?)
+    }
+
+    fn unlink(path: UserCStr) -> Result<(), Errno> {
+        let mut path_buf = [0u8; PATH_MAX];
+        fs::unlink(copy_str_from_user(&mut path_buf, path.addr)?)
+    }
+
+    fn rmdir(path: UserCStr) -> Result<(), Errno> {
+        let mut path_buf = [0u8; PATH_MAX];
+        fs::rmdir(copy_str_from_user(&mut path_buf, path.addr)?)
+    }
+
+    fn rename(old: UserCStr, new: UserCStr) -> Result<(), Errno> {
+        let (mut old_buf, mut new_buf) = ([0u8; PATH_MAX], [0u8; PATH_MAX]);
+        let old = copy_str_from_user(&mut old_buf, old.addr)?;
+        let new = copy_str_from_user(&mut new_buf, new.addr)?;
+        fs::rename(old, new)
+    }
+
+    fn readdir(fd: usize, dirent: UserBufMut) -> Result<usize, Errno> {
+        if dirent.len != core::mem::size_of::<Dirent>() {
+            return Err(Errno::EINVAL);
+        }
+        let Some(entry) = current_file(fd)?.readdir()? else {
+            return Ok(0);
+        };
+        if entry.name.len() > NAME_MAX {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        let mut out = Dirent {
+            kind: entry.kind,
+            name_len: entry.name.len(),
+            ..Default::default()
+        };
+        out.name[..entry.name.len()].copy_from_slice(entry.name.as_bytes());
+        write_user(dirent.addr, out)?;
+        Ok(1)
+    }
+}
+
+fn sbi_errno(err: SbiError) -> Errno {
+    match err {
+        SbiError::NotSupported => Errno::ENOSYS,
+        _ => Errno::EIO,
+    }
+}
@@ -1,5 +0,0 @@
-pub const SYS_PUTCHAR: usize = 1;
-pub const SYS_GETCHAR: usize = 2;
-// pub const SYS_READFILE: usize = 3;
-// pub const SYS_WRITEFILE: usize = 4;
-pub const SYS_EXIT: usize = 3;
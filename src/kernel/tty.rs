@@ -0,0 +1,316 @@
+//! Console TTY: the line discipline between the console and processes.
+//!
+//! The received bytes are processed by [`receive`] in the console interrupt handler, so the echo
+//! doesn't wait for a reader. The mode is switched by the `ioctl` system call. (see [`abi::tty`])
+//!
+//! In the canonical mode, the edited line is moved to the readable queue when it is completed by
+//! NL or Ctrl-D. The line editing is UTF-8 aware: backspace erases a character, not a byte.
+use crate::console::{self, Console};
+use crate::proc::{sleep, WaitChannel};
+use crate::uaccess::{copy_from_user, copy_to_user};
+use abi::tty::{TtyMode, TTY_GET_MODE, TTY_SET_MODE};
+use abi::Errno;
+use core::cell::UnsafeCell;
+use kernel::ring_buffer::RingBuffer;
+
+/// DEL: The backspace key of most terminals
+const ERASE: u8 = 0x7f;
+const BACKSPACE: u8 = 0x08;
+/// Ctrl-U
+const KILL: u8 = 0x15;
+/// Ctrl-D (End Of Transmission)
+pub const EOT: u8 = 0x04;
+
+/// Max line length including NL
+const LINE_MAX: usize = 256;
+const READY_LEN: usize = 512;
+/// User buffers are copied via the kernel stack by this size.
+const CHUNK_LEN: usize = 256;
+
+struct Tty {
+    mode: TtyMode,
+    /// Line being edited (canonical mode)
+    line: [u8; LINE_MAX],
+    line_len: usize,
+    /// The leading byte of the receiving character was dropped.
+    drop_continuation: bool,
+    /// Readable input. `None`: the end of a line completed by Ctrl-D
+    ready: RingBuffer<Option<u8>, READY_LEN>,
+}
+
+impl Tty {
+    fn receive(&mut self, byte: u8) {
+        let byte = match byte {
+            b'\r' if self.mode.contains(TtyMode::ICRNL) => b'\n',
+            byte => byte,
+        };
+
+        if !self.mode.contains(TtyMode::CANONICAL) {
+            // If full, the input is dropped.
+            if self.ready.push(Some(byte)) {
+                self.echo(byte);
+            }
+            return;
+        }
+
+        match byte {
+            ERASE | BACKSPACE => self.erase(),
+            KILL => {
+                while self.line_len > 0 {
+                    self.erase();
+                }
+            }
+            EOT => self.complete_line(false),
+            b'\n' => {
+                self.line[self.line_len] = byte;
+                self.line_len += 1;
+                self.echo(byte);
+                self.complete_line(true);
+            }
+            _ => self.push_utf8(byte),
+        }
+    }
+
+    /// Append a byte of UTF-8 text to the line.
+    ///
+    /// Like Linux, the characters overflowing the line are dropped. A multi-byte character is
+    /// dropped as a whole, so the line never ends with a broken character.
+    fn push_utf8(&mut self, byte: u8) {
+        if is_continuation(byte) {
+            if self.drop_continuation {
+                return;
+            }
+        } else {
+            // Keep the last slot for NL.
+            self.drop_continuation = self.line_len + utf8_len(byte) > LINE_MAX - 1;
+            if self.drop_continuation {
+                return;
+            }
+        }
+        if self.line_len < LINE_MAX - 1 {
+            self.line[self.line_len] = byte;
+            self.line_len += 1;
+            self.echo(byte);
+        }
+    }
+
+    /// Erase the last character(not byte) of the line.
+    fn erase(&mut self) {
+        if self.line_len == 0 {
+            return;
+        }
+        // The continuation bytes & the leading byte of a multi-byte character
+        while self.line_len > 1 && is_continuation(self.line[self.line_len - 1]) {
+            self.line_len -= 1;
+        }
+        self.line_len -= 1;
+        if self.mode.contains(TtyMode::ECHO) {
+            // `^X` is 2 columns. (East Asian wide characters are not considered.)
+            let width = match is_control(self.line[self.line_len]) {
+                true => 2,
+                false => 1,
+            };
+            for _ in 0..width {
+                self.write(&mut console::lock(), b"\x08 \x08");
+            }
+        }
+    }
+
+    /// Move the edited line to the readable queue.
+    fn complete_line(&mut self, with_newline: bool) {
+        let len = self.line_len + !with_newline as usize;
+        // If the line doesn't fit, the whole line is dropped.
+        if READY_LEN - self.ready.len() >= len {
+            for byte in &self.line[..self.line_len] {
+                self.ready.push(Some(*byte));
+            }
+            if !with_newline {
+                self.ready.push(None);
+            }
+        }
+        self.line_len = 0;
+    }
+
+    fn echo(&mut self, byte: u8) {
+        if !self.mode.contains(TtyMode::ECHO) {
+            return;
+        }
+        match is_control(byte) {
+            true => self.write(&mut console::lock(), &[b'^', byte ^ 0x40]),
+            false => self.write(&mut console::lock(), &[byte]),
+        }
+    }
+
+    /// # Return
+    /// `None` if no input is readable.
+    fn read_ready(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.ready.is_empty() {
+            return None;
+        }
+        let canonical = self.mode.contains(TtyMode::CANONICAL);
+        let mut read = 0;
+        let mut line_ended = false;
+        while read < buf.len() {
+            match self.ready.pop() {
+                Some(Some(byte)) => {
+                    buf[read] = byte;
+                    read += 1;
+                    if canonical && byte == b'\n' {
+                        line_ended = true;
+                        break;
+                    }
+                }
+                // End of the line by Ctrl-D (0 byte at the empty line: end of file)
+                Some(None) => {
+                    line_ended = true;
+                    break;
+                }
+                None => break,
+            }
+        }
+        // The line ended by Ctrl-D exactly filled `buf`: consume the end marker with it.
+        // (Otherwise the next read sees it as the end of file.)
+        if read > 0 && !line_ended && self.ready.peek() == Some(None) {
+            self.ready.pop();
+        }
+        Some(read)
+    }
+
+    fn write(&self, console: &mut Console, bytes: &[u8]) {
+        if !self.mode.contains(TtyMode::ONLCR) {
+            console.write_bytes(bytes);
+            return;
+        }
+        for (i, segment) in bytes.split(|byte| *byte == b'\n').enumerate() {
+            if i != 0 {
+                console.write_bytes(b"\r\n");
+            }
+            console.write_bytes(segment);
+        }
+    }
+
+    fn set_mode(&mut self, mode: TtyMode) {
+        // The edited line becomes readable as is.
+        if !mode.contains(TtyMode::CANONICAL) {
+            for byte in &self.line[..self.line_len] {
+                self.ready.push(Some(*byte));
+            }
+            self.line_len = 0;
+        }
+        self.mode = mode;
+    }
+}
+
+/// `10xx_xxxx`: 2nd.. byte of a UTF-8 multi-byte character
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
+/// Byte length of the UTF-8 character starting with `lead`. (1 if invalid)
+fn utf8_len(lead: u8) -> usize {
+    match lead.leading_ones() {
+        2 => 2,
+        3 => 3,
+        4 => 4,
+        _ => 1,
+    }
+}
+
+/// Echoed as `^X` (except NL & TAB)
+fn is_control(byte: u8) -> bool {
+    (byte < 0x20 && !matches!(byte, b'\n' | b'\t')) || byte == ERASE
+}
+
+struct TtyCell(UnsafeCell<Tty>);
+
+/// Only accessed in S-Mode, where interrupts are disabled. (single hart)
+unsafe impl Sync for TtyCell {}
+
+impl TtyCell {
+    fn with<R>(&self, f: impl FnOnce(&mut Tty) -> R) -> R {
+        f(unsafe { &mut *self.0.get() })
+    }
+}
+
+static TTY: TtyCell = TtyCell(UnsafeCell::new(Tty {
+    mode: TtyMode::DEFAULT,
+    line: [0; LINE_MAX],
+    line_len: 0,
+    drop_continuation: false,
+    ready: RingBuffer::new(None),
+}));
+
+/// Process one received byte. (Called by the console interrupt handler)
+///
+/// # Return
+/// Is input readable?
+pub fn receive(byte: u8) -> bool {
+    TTY.with(|tty| {
+        tty.receive(byte);
+        !tty.ready.is_empty()
+    })
+}
+
+/// Read the input. If there is no input, sleep until it arrives.
+///
+/// In the canonical mode, up to one line is read.
+///
+/// # Return
+/// Read bytes. (0: end of file)
+pub fn read(buf: &mut [u8]) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+    loop {
+        if let Some(read) = TTY.with(|tty| tty.read_ready(buf)) {
+            return read;
+        }
+        sleep(WaitChannel::ConsoleInput);
+    }
+}
+
+/// Write with the output processing of the mode.
+pub fn write(bytes: &[u8]) {
+    TTY.with(|tty| tty.write(&mut console::lock(), bytes));
+}
+
+/// [`read`] to the user buffer `[buf, buf + len)`.
+///
+/// # Errors
+/// EFAULT
+pub fn read_to_user(buf: usize, len: usize) -> Result<usize, Errno> {
+    let mut chunk = [0u8; CHUNK_LEN];
+    let read = read(&mut chunk[..len.min(CHUNK_LEN)]);
+    copy_to_user(buf, &chunk[..read])?;
+    Ok(read)
+}
+
+/// [`write`] the user buffer `[buf, buf + len)`.
+///
+/// # Errors
+/// EFAULT (The bytes before the fault may have been written.)
+pub fn write_from_user(buf: usize, len: usize) -> Result<usize, Errno> {
+    let mut chunk = [0u8; CHUNK_LEN];
+    let mut written = 0;
+    // Not interleaved with others even if longer than a chunk.
+    let mut console = console::lock();
+    while written < len {
+        let chunk = &mut chunk[..(len - written).min(CHUNK_LEN)];
+        copy_from_user(chunk, buf + written)?;
+        TTY.with(|tty| tty.write(&mut console, chunk));
+        written += chunk.len();
+    }
+    Ok(len)
+}
+
+/// `ioctl` on the console
+pub fn ioctl(request: usize, arg: usize) -> Result<usize, Errno> {
+    match request {
+        TTY_GET_MODE => Ok(TTY.with(|tty| tty.mode.bits() as usize)),
+        TTY_SET_MODE => {
+            TTY.with(|tty| tty.set_mode(TtyMode::from_bits(arg as u32)));
+            Ok(0)
+        }
+        _ => Err(Errno::EINVAL),
+    }
+}
@@ -0,0 +1,113 @@
+//! Safe access to user memory from system calls.
+//!
+//! The user range is validated by walking the running process's page table
+//! (mapped, `U` bit & `R`/`W` bit) before touching it, and `sstatus.SUM` is set only while copying.
+//! So a bad user pointer becomes `EFAULT` instead of a kernel page fault.
+use crate::pages::{translate, PAGE_R, PAGE_SIZE, PAGE_U, PAGE_W};
+use crate::proc::with_current_proc;
+use abi::Errno;
+use kernel::addr::align_down;
+use kernel::riscv::sstatus;
+
+/// User pointers must be below this. (The kernel is identity mapped from here.)
+const USER_LIMIT: usize = 0x8000_0000;
+
+/// Check that every page of `[addr, addr + len)` is a user page with `flags`.
+///
+/// # Errors
+/// EFAULT
+fn check_user_range(addr: usize, len: usize, flags: usize) -> Result<(), Errno> {
+    if len == 0 {
+        return Ok(());
+    }
+    let end = addr
+        .checked_add(len)
+        .filter(|end| *end <= USER_LIMIT)
+        .ok_or(Errno::EFAULT)?;
+
+    let root_ppn = with_current_proc(|proc| proc.page_table());
+    let mut page = align_down(addr, PAGE_SIZE);
+    while page < end {
+        match translate(root_ppn, page) {
+            Some((_, page_flags)) if page_flags & (flags | PAGE_U) == flags | PAGE_U => {}
+            _ => return Err(Errno::EFAULT),
+        }
+        page += PAGE_SIZE;
+    }
+    Ok(())
+}
+
+/// Run `f` with permitting S-Mode to access U-Mode pages.
+fn with_sum<R>(f: impl FnOnce() -> R) -> R {
+    unsafe { sstatus::set(sstatus::SUM) };
+    let ret = f();
+    unsafe { sstatus::clear(sstatus::SUM) };
+    ret
+}
+
+/// Copy `dst.len()` bytes from the user address `src`.
+///
+/// # Errors
+/// EFAULT: `src` range is not readable user memory.
+pub fn copy_from_user(dst: &mut [u8], src: usize) -> Result<(), Errno> {
+    check_user_range(src, dst.len(), PAGE_R)?;
+    with_sum(|| unsafe {
+        core::ptr::copy_nonoverlapping(src as *const u8, dst.as_mut_ptr(), dst.len());
+    });
+    Ok(())
+}
+
+/// Copy `src` to the user address `dst`.
+///
+/// # Errors
+/// EFAULT: `dst` range is not writable user memory.
+pub fn copy_to_user(dst: usize, src: &[u8]) -> Result<(), Errno> {
+    check_user_range(dst, src.len(), PAGE_W)?;
+    with_sum(|| unsafe {
+        core::ptr::copy_nonoverlapping(src.as_ptr(), dst as *mut u8, src.len());
+    });
+    Ok(())
+}
+
+/// Read a plain value(no pointers/references inside) from the user address `src`.
+///
+/// # Errors
+/// EFAULT
+pub fn read_user<T: Copy>(src: usize) -> Result<T, Errno> {
+    check_user_range(src, core::mem::size_of::<T>(), PAGE_R)?;
+    Ok(with_sum(|| unsafe { (src as *const T).read_unaligned() }))
+}
+
+/// Write a plain value(no pointers/references inside) to the user address `dst`.
+///
+/// # Errors
+/// EFAULT
+pub fn write_user<T: Copy>(dst: usize, value: T) -> Result<(), Errno> {
+    check_user_range(dst, core::mem::size_of::<T>(), PAGE_W)?;
+    with_sum(|| unsafe { (dst as *mut T).write_unaligned(value) });
+    Ok(())
+}
+
+/// Copy a NUL terminated string from the user address `src` into `buf`.
+///
+/// # Errors
+/// - EFAULT: not readable user memory
+/// - ENAMETOOLONG: no NUL within `buf.len()` bytes
+/// - EINVAL: not UTF-8
+pub fn copy_str_from_user(buf: &mut [u8], src: usize) -> Result<&str, Errno> {
+    if src >= USER_LIMIT {
+        return Err(Errno::EFAULT);
+    }
+    // Copy page by page until NUL, not to read beyond the mapped range.
+    let mut len = 0;
+    while len < buf.len() {
+        let chunk_end = (align_down(src + len, PAGE_SIZE) + PAGE_SIZE).min(src + buf.len());
+        let chunk = &mut buf[len..chunk_end - src];
+        copy_from_user(chunk, src + len)?;
+        if let Some(nul) = chunk.iter().position(|byte| *byte == 0) {
+            return core::str::from_utf8(&buf[..len + nul]).map_err(|_| Errno::EINVAL);
+        }
+        len = chunk_end - src;
+    }
+    Err(Errno::ENAMETOOLONG)
+}
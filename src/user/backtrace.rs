@@ -0,0 +1,53 @@
+//! User panic backtrace by walking the frame pointer chain.
+//!
+//! Frames are laid out as in the kernel(see `src/kernel/backtrace.rs`), and the return addresses
+//! are resolved with the symbol table that the kernel's `build.rs` appends to each app at `__syms`.
+use crate::println;
+use abi::symbols::SymbolTable;
+use core::arch::asm;
+
+extern "C" {
+    /// This symbol is defined by user.ld.
+    fn __syms();
+    /// This symbol is defined by user.ld.
+    fn __stack_top();
+}
+
+/// Base address of the app (user.ld)
+const USER_BASE: usize = 0x1000000;
+const MAX_DEPTH: usize = 32;
+
+fn print_frame(depth: usize, ra: usize, symbols: Option<&SymbolTable>) {
+    // `ra` is the next of `call`, so resolve `ra - 1`.
+    match symbols.and_then(|symbols| symbols.lookup(ra - 1)) {
+        Some(symbol) => println!(
+            "  #{} {:#010x} {}+{:#x}",
+            depth,
+            ra,
+            symbol.name,
+            symbol.offset + 1
+        ),
+        None => println!("  #{} {:#010x} ??", depth, ra),
+    }
+}
+
+/// Print the backtrace of the caller.
+#[inline(never)]
+pub fn print_backtrace() {
+    let symbols = unsafe { SymbolTable::from_ptr(__syms as *const u8) };
+    let mut fp: usize;
+    unsafe { asm!("mv {}, fp", out(reg) fp) };
+
+    println!("backtrace:");
+    let stack_range = (USER_BASE + 8)..=__stack_top as usize;
+    let mut depth = 0;
+    while depth < MAX_DEPTH && fp % 4 == 0 && stack_range.contains(&fp) {
+        let ra = unsafe { ((fp - 4) as *const usize).read() };
+        if ra == 0 {
+            break;
+        }
+        print_frame(depth, ra, symbols.as_ref());
+        fp = unsafe { ((fp - 8) as *const usize).read() };
+        depth += 1;
+    }
+}
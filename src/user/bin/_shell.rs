@@ -1,48 +1,254 @@
 #![no_std]
 #![no_main]
 
-use user_lib::{exit, get_char, print, println};
+use user_lib::{
+    exit, mkdir, open, print, println, readdir, readfile, rename, rmdir, sys, unlink, writefile,
+    Errno, FileKind, OpenFlags, SEEK_END,
+};
 
 #[no_mangle]
 pub fn main() {
     println!("---------------------------------");
-    println!("executable cmd: ['hello', 'exit']");
+    println!(
+        "executable cmd: ['hello', 'readfile', 'writefile', 'tmpfs-test', 'dmesg', 'exit', 'shutdown', 'reboot']"
+    );
+    println!(
+        "file cmd: ['ls [PATH]', 'cat PATH', 'write PATH TEXT', 'mkdir PATH', 'rm PATH', 'rmdir PATH', 'mv OLD NEW']"
+    );
     println!("---------------------------------");
     loop {
         print!("> ");
         // Caution! :Currently, the stack provides everything. And it's not freed up,
         // so if you type 8 characters or so, you'll panic 100% because there's not enough kernel stack saved by the context switch.
         let mut cmd_line = [0u8; 228];
-        let mut i = 0;
-
-        loop {
-            let ch = get_char() as u8;
-            print!("{}", core::str::from_utf8(&[ch]).unwrap());
-
-            if i == cmd_line.len() - 1 {
-                println!("");
-                println!("command line too long");
-                break;
-            } else if ch == b'\r' {
-                println!("");
-                let cmd_str = core::str::from_utf8(&cmd_line).unwrap_or("invalid utf-8");
-                // match cmd_str {
-                //     "hello" => println!("Hello world from shell!"),
-                //     "exit" => exit(),
-                //     _ => println!("unknown command: {}", cmd_str),
-                // };
-                if cmd_str.contains("hello") {
-                    println!("Hello world from shell!")
-                } else if cmd_str.contains("exit") {
-                    exit()
-                } else {
-                    println!("unknown command: {}", cmd_str)
+        // The TTY echoes & edits the line, and returns it after Enter.
+        let len = sys::read(0, (&mut cmd_line[..]).into()).expect("failed to read stdin");
+        if len == 0 {
+            // Ctrl-D on an empty line
+            println!("");
+            exit(0)
+        }
+        if len == cmd_line.len() && cmd_line[len - 1] != b'\n' {
+            println!("command line too long");
+            // Discard the rest of the line.
+            let mut rest = [0u8; 64];
+            while let Ok(len @ 1..) = sys::read(0, (&mut rest[..]).into()) {
+                // A short read is the end of the line. (NL or Ctrl-D)
+                if len < rest.len() || rest[len - 1] == b'\n' {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        let cmd_str = core::str::from_utf8(&cmd_line[..len]).unwrap_or("invalid utf-8");
+        match cmd_str.trim() {
+            "" => {}
+            "hello" => println!("Hello world from shell!"),
+            "readfile" => {
+                let mut buf = [0u8; 128];
+                match readfile("hello.txt", &mut buf) {
+                    Ok(len) => print!("{}", core::str::from_utf8(&buf[..len]).unwrap_or("?")),
+                    Err(err) => println!("readfile failed: {}", err),
+                }
+            }
+            "writefile" => {
+                if let Err(err) = writefile("hello.txt", b"Hello from shell!\n") {
+                    println!("writefile failed: {}", err)
+                }
+            }
+            "tmpfs-test" => match tmpfs_test() {
+                true => println!("tmpfs-test: ok"),
+                false => println!("tmpfs-test: FAILED"),
+            },
+            "dmesg" => {
+                let mut log = [0u8; 4096];
+                match sys::dmesg((&mut log[..]).into()) {
+                    Ok(len) => print!("{}", core::str::from_utf8(&log[..len]).unwrap_or("")),
+                    Err(err) => println!("dmesg failed: {}", err),
+                }
+            }
+            "shutdown" => {
+                let err = sys::shutdown().unwrap_err();
+                println!("shutdown failed: {}", err)
+            }
+            "reboot" => {
+                let err = sys::reboot().unwrap_err();
+                println!("reboot failed: {}", err)
+            }
+            "exit" => exit(0),
+            cmd => {
+                if !file_command(cmd) {
+                    println!("unknown command: {}", cmd)
                 }
-                break;
-            } else {
-                cmd_line[i] = ch;
-                i += 1;
             }
         }
     }
 }
+
+/// Run the file command `line`. (with arguments)
+///
+/// # Return
+/// `false` if `line` is not a file command.
+fn file_command(line: &str) -> bool {
+    let (cmd, args) = match line.split_once(' ') {
+        Some((cmd, args)) => (cmd, args.trim()),
+        None => (line, ""),
+    };
+    let result = match (cmd, args.split_once(' ')) {
+        ("ls", None) => ls(if args.is_empty() { "/" } else { args }),
+        ("cat", None) if !args.is_empty() => cat(args),
+        ("mkdir", None) if !args.is_empty() => mkdir(args),
+        ("rm", None) if !args.is_empty() => unlink(args),
+        ("rmdir", None) if !args.is_empty() => rmdir(args),
+        ("mv", Some((old, new))) => rename(old, new.trim()),
+        ("write", Some((path, text))) => write(path, text),
+        _ => return false,
+    };
+    if let Err(err) = result {
+        println!("{}: {}", cmd, err)
+    }
+    true
+}
+
+/// List the directory `path`. (Directories end with `/`)
+fn ls(path: &str) -> Result<(), Errno> {
+    let fd = open(path, OpenFlags::RDONLY)?;
+    let result = loop {
+        match readdir(fd) {
+            Ok(Some(entry)) => match entry.kind {
+                FileKind::Directory => println!("{}/", entry.name()),
+                _ => println!("{}", entry.name()),
+            },
+            Ok(None) => break Ok(()),
+            Err(err) => break Err(err),
+        }
+    };
+    sys::close(fd)?;
+    result
+}
+
+/// Replace the file `path` with `text` & NL.
+fn write(path: &str, text: &str) -> Result<(), Errno> {
+    let fd = open(
+        path,
+        OpenFlags::WRONLY | OpenFlags::CREAT | OpenFlags::TRUNC,
+    )?;
+    let result =
+        sys::write(fd, text.as_bytes().into()).and_then(|_| sys::write(fd, b"\n"[..].into()));
+    sys::close(fd)?;
+    result.map(|_| ())
+}
+
+/// Print the file `path`.
+fn cat(path: &str) -> Result<(), Errno> {
+    let fd = open(path, OpenFlags::RDONLY)?;
+    let mut buf = [0u8; 128];
+    let result = loop {
+        match sys::read(fd, (&mut buf[..]).into()) {
+            Ok(0) => break Ok(()),
+            Ok(len) => print!("{}", core::str::from_utf8(&buf[..len]).unwrap_or("?")),
+            Err(err) => break Err(err),
+        }
+    };
+    sys::close(fd)?;
+    result
+}
+
+/// Exercise the tmpfs at `/tmp`: rename replacing, non empty directories & the page limit.
+///
+/// # Return
+/// `false` if any check failed. (Each failure is printed.)
+fn tmpfs_test() -> bool {
+    let ok = &mut true;
+
+    check(ok, "mkdir", mkdir("/tmp/t"), Ok(()));
+    check(ok, "write a", write("/tmp/t/a", "A"), Ok(()));
+    check(ok, "write b", write("/tmp/t/b", "B"), Ok(()));
+    check(ok, "rename over b", rename("/tmp/t/a", "/tmp/t/b"), Ok(()));
+    let mut buf = [0u8; 8];
+    let content = readfile("/tmp/t/b", &mut buf).map(|len| buf[..len] == *b"A\n");
+    check(ok, "replaced b", content, Ok(true));
+    let old = open("/tmp/t/a", OpenFlags::RDONLY).map(|_| ());
+    check(ok, "old name", old, Err(Errno::ENOENT));
+    check(
+        ok,
+        "rmdir non empty",
+        rmdir("/tmp/t"),
+        Err(Errno::ENOTEMPTY),
+    );
+
+    check(ok, "mkdir d", mkdir("/tmp/t/d"), Ok(()));
+    check(ok, "mkdir e", mkdir("/tmp/t/e"), Ok(()));
+    check(ok, "write d/f", write("/tmp/t/d/f", "F"), Ok(()));
+    let cases = [
+        ("file over dir", "/tmp/t/b", "/tmp/t/d", Errno::EISDIR),
+        ("dir over file", "/tmp/t/e", "/tmp/t/b", Errno::ENOTDIR),
+        (
+            "dir over non empty",
+            "/tmp/t/e",
+            "/tmp/t/d",
+            Errno::ENOTEMPTY,
+        ),
+        (
+            "file over its parent",
+            "/tmp/t/d/f",
+            "/tmp/t/d",
+            Errno::EISDIR,
+        ),
+        ("dir into itself", "/tmp/t/d", "/tmp/t/d/x", Errno::EINVAL),
+    ];
+    for (what, old, new, errno) in cases {
+        check(ok, what, rename(old, new), Err(errno));
+    }
+
+    // Use up the pages (1MiB), then free 1 page.
+    let big = "/tmp/t/big";
+    check(ok, "page limit", fill(big, 4096), Err(Errno::ENOSPC));
+    check(ok, "unlink b", unlink("/tmp/t/b"), Ok(()));
+    // The 2 pages of a write after a 1 page gap don't fit. The failed write must not keep
+    // the 1st page, or another file can't use it.
+    let gap_write = || -> Result<(), Errno> {
+        let fd = open(big, OpenFlags::WRONLY)?;
+        let result = sys::lseek(fd, PAGE_SIZE as isize, SEEK_END)
+            .and_then(|_| sys::write(fd, b"x"[..].into()));
+        sys::close(fd)?;
+        result.map(|_| ())
+    };
+    check(ok, "2 pages", gap_write(), Err(Errno::ENOSPC));
+    check(ok, "1 page", write("/tmp/t/c", "C"), Ok(()));
+    check(ok, "unlink big", unlink(big), Ok(()));
+    check(ok, "freed pages", fill(big, 8), Ok(()));
+
+    check(ok, "unlink big", unlink(big), Ok(()));
+    for file in ["/tmp/t/c", "/tmp/t/d/f"] {
+        check(ok, file, unlink(file), Ok(()));
+    }
+    for dir in ["/tmp/t/d", "/tmp/t/e", "/tmp/t"] {
+        check(ok, dir, rmdir(dir), Ok(()));
+    }
+    *ok
+}
+
+/// The tmpfs allocates file data by this size.
+const PAGE_SIZE: usize = 4096;
+
+/// Print & clear `ok` if `result` is not `expected`.
+fn check<T: PartialEq + core::fmt::Debug>(ok: &mut bool, what: &str, result: T, expected: T) {
+    if result != expected {
+        println!("{}: {:?} (expected {:?})", what, result, expected);
+        *ok = false;
+    }
+}
+
+/// Append `chunks` × 512 bytes to the file `path`.
+fn fill(path: &str, chunks: usize) -> Result<(), Errno> {
+    let fd = open(
+        path,
+        OpenFlags::WRONLY | OpenFlags::CREAT | OpenFlags::APPEND,
+    )?;
+    let chunk = [0x55u8; 512];
+    let result = (0..chunks).try_for_each(|_| sys::write(fd, chunk[..].into()).map(|_| ()));
+    sys::close(fd)?;
+    result
+}
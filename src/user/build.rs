@@ -1,6 +1,6 @@
 use std::fs::{read_dir, File};
-use std::io::{Result, Write};
-use std::path::Path;
+use std::io::{Read, Result, Write};
+use std::path::{Path, PathBuf};
 
 const TARGET_PATH: &str = "target/riscv32imac-unknown-none-elf/release/";
 /// 64bit: quad, 32bit: word
@@ -29,10 +29,12 @@ fn create_app_data(manifest_dir: &Path) -> Result<()> {
         .map(|dir_entry| {
             let mut name_with_ext = dir_entry.unwrap().file_name().into_string().unwrap();
             name_with_ext.drain(name_with_ext.find('.').unwrap()..name_with_ext.len());
-            name_with_ext
+            let path = format!("{TARGET_PATH}{name_with_ext}.bin");
+            (name_with_ext, path)
         })
         .collect();
     apps.sort();
+    apps.extend(linux_apps(&manifest_dir.join("linux")));
 
     writeln!(
         f,
@@ -50,7 +52,7 @@ _num_app:
     }
     writeln!(f, r#"    .{PTR_SIZE} app_{}_end"#, apps.len() - 1)?;
 
-    for (idx, app) in apps.iter().enumerate() {
+    for (idx, (app, path)) in apps.iter().enumerate() {
         println!("app_{}: {}", idx, app);
 
         writeln!(
@@ -60,10 +62,37 @@ _num_app:
     .global app_{0}_start
     .global app_{0}_end
 app_{0}_start:
-    .incbin "{2}{1}.bin"
+    .incbin "{1}"
 app_{0}_end:"#,
-            idx, app, TARGET_PATH
+            idx, path
         )?;
     }
     Ok(())
 }
+
+/// Prebuilt statically linked riscv32 Linux executables are embedded as is(ELF).
+/// The kernel runs them with the Linux personality.
+fn linux_apps(linux_dir: &Path) -> Vec<(String, String)> {
+    println!("cargo:rerun-if-changed={}", linux_dir.display());
+    let Ok(entries) = read_dir(linux_dir) else {
+        return Vec::new();
+    };
+    let mut apps: Vec<_> = entries
+        .map(|dir_entry| dir_entry.unwrap().path())
+        .filter(|path| is_elf(path))
+        .map(|path: PathBuf| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, path.display().to_string())
+        })
+        .collect();
+    apps.sort();
+    apps
+}
+
+fn is_elf(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && &magic == b"\x7fELF"
+}
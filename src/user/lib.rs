@@ -3,46 +3,60 @@
 #![feature(naked_functions)]
 #![feature(panic_info_message)]
 
+mod backtrace;
+
+pub use abi::fs::{Dirent, FileKind, OpenFlags, Stat, SEEK_CUR, SEEK_END, SEEK_SET};
+pub use abi::{user as sys, Errno};
 use core::{
     arch::asm,
     fmt::{self, Write},
     panic::PanicInfo,
 };
-use kernel::syscall_num::{SYS_EXIT, SYS_GETCHAR, SYS_PUTCHAR};
 
-#[inline(always)]
-fn syscall(sysno: usize, arg0: usize, arg1: usize, arg2: usize) -> isize {
-    let mut ret: isize;
-    unsafe {
-        // x10: a0, x11: a1, x12: a2 -> x10: system call result
-        asm!(
-            "ecall",
-            inlateout("a0") arg0 => ret,
-            in("a1") arg1,
-            in("a2") arg2,
-            in("a3") sysno
-        );
-    }
-    ret
-}
+const STDOUT: usize = 1;
+const STDOUT_BUF_LEN: usize = 256;
 
-pub fn put_char(ch: char) {
-    syscall(SYS_PUTCHAR, ch as usize, 0, 0);
+/// Buffered stdout: one `write` syscall per `STDOUT_BUF_LEN` bytes instead of per char.
+struct Stdout {
+    buf: [u8; STDOUT_BUF_LEN],
+    len: usize,
 }
 
-struct Stdout;
+impl Stdout {
+    fn flush(&mut self) {
+        let mut written = 0;
+        while written < self.len {
+            match sys::write(STDOUT, self.buf[written..self.len].into()) {
+                Ok(len) if len > 0 => written += len,
+                // Nowhere to report.
+                _ => break,
+            }
+        }
+        self.len = 0;
+    }
+}
 
 impl fmt::Write for Stdout {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for c in s.chars() {
-            put_char(c);
+        for byte in s.bytes() {
+            if self.len == self.buf.len() {
+                self.flush();
+            }
+            self.buf[self.len] = byte;
+            self.len += 1;
         }
         Ok(())
     }
 }
 
+/// Print `args`. The output is flushed before return.
 pub fn print(args: fmt::Arguments) {
-    Stdout.write_fmt(args).unwrap();
+    let mut stdout = Stdout {
+        buf: [0; STDOUT_BUF_LEN],
+        len: 0,
+    };
+    stdout.write_fmt(args).unwrap();
+    stdout.flush();
 }
 
 #[macro_export]
@@ -59,32 +73,125 @@ macro_rules! println {
     }
 }
 
-/// Read stdin
+/// Read one UTF-8 character from stdin. (For the raw mode TTY, which returns bytes as typed)
+///
+/// An invalid byte sequence is decoded as U+FFFD.
 ///
-/// #Return
-/// - get => char
-/// - if get nothing => loop in kernel
-pub fn get_char() -> usize {
-    syscall(SYS_GETCHAR, 0, 0, 0) as usize
-}
-
-// pub fn readfile(filename: &str, buf: &mut [u8]) -> isize {
-//     let filename_ptr = filename.as_ptr() as usize;
-//     let buf_ptr = buf.as_mut_ptr() as usize;
-//     let len = buf.len();
-//     syscall(SYS_READFILE, filename_ptr, buf_ptr, len)
-// }
-
-// pub fn writefile(filename: &str, buf: &[u8]) -> isize {
-//     let filename_ptr = filename.as_ptr() as usize;
-//     let buf_ptr = buf.as_ptr() as usize;
-//     let len = buf.len();
-//     syscall(SYS_WRITEFILE, filename_ptr, buf_ptr, len)
-// }
+/// # Return
+/// `None` at the end of file(Ctrl-D).
+pub fn read_char() -> Option<char> {
+    const EOT: u8 = 0x04;
+    let lead = sys::get_char().ok().filter(|byte| *byte != EOT)?;
+    let len = match lead.leading_ones() {
+        0 => return Some(lead as char),
+        2 => 2,
+        3 => 3,
+        4 => 4,
+        _ => return Some(char::REPLACEMENT_CHARACTER),
+    };
+    let mut bytes = [lead, 0, 0, 0];
+    for byte in bytes[1..len].iter_mut() {
+        *byte = sys::get_char().ok()?;
+    }
+    Some(
+        core::str::from_utf8(&bytes[..len])
+            .ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER),
+    )
+}
 
-#[no_mangle]
-pub extern "C" fn exit() {
-    syscall(SYS_EXIT, 0, 0, 0);
+/// Read the file `path` to `buf`. (see [`sys::readfile`])
+pub fn readfile(path: &str, buf: &mut [u8]) -> Result<usize, Errno> {
+    let mut path_buf = [0u8; PATH_MAX];
+    sys::readfile(to_cstr(path, &mut path_buf)?.into(), buf.into())
+}
+
+/// Replace the content of the file `path` with `buf`. (see [`sys::writefile`])
+pub fn writefile(path: &str, buf: &[u8]) -> Result<usize, Errno> {
+    let mut path_buf = [0u8; PATH_MAX];
+    sys::writefile(to_cstr(path, &mut path_buf)?.into(), buf.into())
+}
+
+/// Open the file `path` & return the fd. (see [`sys::open`])
+pub fn open(path: &str, flags: OpenFlags) -> Result<usize, Errno> {
+    let mut path_buf = [0u8; PATH_MAX];
+    sys::open(to_cstr(path, &mut path_buf)?.into(), flags)
+}
+
+/// File kind & size of `fd`. (see [`sys::fstat`])
+pub fn fstat(fd: usize) -> Result<Stat, Errno> {
+    let mut stat = Stat::default();
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(
+            &mut stat as *mut Stat as *mut u8,
+            core::mem::size_of::<Stat>(),
+        )
+    };
+    sys::fstat(fd, buf.into())?;
+    Ok(stat)
+}
+
+/// Next entry of the directory `fd`. `None` at the end. (see [`sys::readdir`])
+pub fn readdir(fd: usize) -> Result<Option<Dirent>, Errno> {
+    let mut dirent = Dirent::default();
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(
+            &mut dirent as *mut Dirent as *mut u8,
+            core::mem::size_of::<Dirent>(),
+        )
+    };
+    match sys::readdir(fd, buf.into())? {
+        0 => Ok(None),
+        _ => Ok(Some(dirent)),
+    }
+}
+
+/// Create the directory `path`. (see [`sys::mkdir`])
+pub fn mkdir(path: &str) -> Result<(), Errno> {
+    let mut path_buf = [0u8; PATH_MAX];
+    sys::mkdir(to_cstr(path, &mut path_buf)?.into())
+}
+
+/// Remove the file `path`. (see [`sys::unlink`])
+pub fn unlink(path: &str) -> Result<(), Errno> {
+    let mut path_buf = [0u8; PATH_MAX];
+    sys::unlink(to_cstr(path, &mut path_buf)?.into())
+}
+
+/// Remove the empty directory `path`. (see [`sys::rmdir`])
+pub fn rmdir(path: &str) -> Result<(), Errno> {
+    let mut path_buf = [0u8; PATH_MAX];
+    sys::rmdir(to_cstr(path, &mut path_buf)?.into())
+}
+
+/// Move `old` to `new`. (see [`sys::rename`])
+pub fn rename(old: &str, new: &str) -> Result<(), Errno> {
+    let (mut old_buf, mut new_buf) = ([0u8; PATH_MAX], [0u8; PATH_MAX]);
+    let old = to_cstr(old, &mut old_buf)?;
+    sys::rename(old.into(), to_cstr(new, &mut new_buf)?.into())
+}
+
+/// Max path bytes including NUL
+const PATH_MAX: usize = 256;
+
+/// Copy `s` with NUL to `buf`.
+fn to_cstr<'a>(s: &str, buf: &'a mut [u8]) -> Result<&'a core::ffi::CStr, Errno> {
+    if s.len() >= buf.len() {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    buf[..s.len()].copy_from_slice(s.as_bytes());
+    buf[s.len()] = 0;
+    core::ffi::CStr::from_bytes_until_nul(buf).map_err(|_| Errno::EINVAL)
+}
+
+/// Exit status of a panicked process. (Same as Rust std)
+pub const PANIC_EXIT_STATUS: i32 = 101;
+
+/// Terminate the calling process with `status`.
+pub fn exit(status: i32) -> ! {
+    let _ = sys::exit(status);
+    unreachable!("exit syscall returned")
 }
 
 #[no_mangle]
@@ -99,6 +206,7 @@ pub extern "C" fn start() {
     unsafe {
         asm!(
             "la sp, {stack_top}",
+            "mv fp, zero", // Terminate the frame pointer chain for backtraces
             "call main",
             stack_top = sym __stack_top, // This symbol is defined by user.ld.
             options(noreturn)
@@ -119,5 +227,6 @@ fn panic(info: &PanicInfo) -> ! {
         }
         None => println!("[user] Panicked: {}", info.message().unwrap()),
     };
-    loop {}
+    backtrace::print_backtrace();
+    exit(PANIC_EXIT_STATUS)
 }